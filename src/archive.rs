@@ -0,0 +1,91 @@
+//! Zip archives
+//!
+//! This module lists the media entries of zip archives and reads them into memory,
+//! so that they can be imported without extracting the archive first.
+
+use std::{
+    fs::File,
+    io::Read as _,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use chrono::{Local, NaiveDateTime, TimeZone as _};
+use zip::ZipArchive;
+
+use crate::import::MemoryFile;
+
+/// Zip archive with a list of its media entries
+pub(crate) struct ZipMedia {
+    /// Opened archive
+    archive: ZipArchive<File>,
+    /// Indices of media entries with their path, the archive path joined with the entry name
+    entries: Vec<(usize, PathBuf)>,
+}
+
+impl ZipMedia {
+    /// Open the zip archive at `path` and list the entries for which `is_media` holds
+    ///
+    /// The entries are sorted by path for a stable import order.
+    /// Entries with names escaping the archive, like `../x.jpg`, are left out.
+    pub(crate) fn open(path: &Path, is_media: impl Fn(&Path) -> bool) -> Result<Self> {
+        let file =
+            File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+        let mut archive = ZipArchive::new(file)
+            .with_context(|| format!("failed to read zip archive {}", path.display()))?;
+
+        let mut entries = Vec::new();
+        for index in 0..archive.len() {
+            let entry = archive.by_index(index)?;
+            if entry.is_dir() {
+                continue;
+            }
+            if let Some(name) = entry.enclosed_name() {
+                let entry_path = path.join(name);
+                if is_media(&entry_path) {
+                    entries.push((index, entry_path));
+                }
+            }
+        }
+
+        entries.sort_by(|(_, a), (_, b)| a.cmp(b));
+        Ok(Self { archive, entries })
+    }
+
+    /// Number of media entries
+    pub(crate) fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Read the media entries into memory one after another
+    pub(crate) fn read_entries(
+        &mut self,
+    ) -> impl Iterator<Item = (PathBuf, Result<MemoryFile>)> + '_ {
+        let Self { archive, entries } = self;
+        entries.iter().map(|(index, path)| {
+            let file = read_entry(archive, *index, path)
+                .with_context(|| format!("failed to read {}", path.display()));
+            (path.clone(), file)
+        })
+    }
+}
+
+/// Read the entry at `index` of `archive` into memory
+fn read_entry(archive: &mut ZipArchive<File>, index: usize, path: &Path) -> Result<MemoryFile> {
+    let mut entry = archive.by_index(index)?;
+    let mut data = Vec::with_capacity(usize::try_from(entry.size()).unwrap_or_default());
+    entry.read_to_end(&mut data)?;
+
+    // Zip archives store the local modification time without offset
+    let modified = entry
+        .last_modified()
+        .and_then(|m| NaiveDateTime::try_from(m).ok())
+        .and_then(|m| Local.from_local_datetime(&m).earliest())
+        .map(|m| m.fixed_offset());
+
+    Ok(MemoryFile {
+        path: path.to_owned(),
+        data,
+        modified,
+    })
+}
@@ -0,0 +1,119 @@
+//! Exif date backfill
+//!
+//! This module writes creation dates into JPEG files without exif data,
+//! so that other tools date them like the importer did.
+
+use std::{fs, path::Path};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, FixedOffset};
+use img_parts::{
+    ImageEXIF as _,
+    jpeg::{Jpeg, JpegSegment, markers},
+};
+
+/// Tag of the pointer from IFD0 to the Exif IFD
+const EXIF_IFD_POINTER: u16 = 0x8769;
+/// Tags of the Exif IFD written by [backfill_date], sorted by number
+const DATE_TIME_ORIGINAL: u16 = 0x9003;
+const CREATE_DATE: u16 = 0x9004;
+const OFFSET_TIME_ORIGINAL: u16 = 0x9011;
+const OFFSET_TIME_DIGITIZED: u16 = 0x9012;
+
+/// TIFF type of text values
+const ASCII: u16 = 2;
+/// TIFF type of 32-bit unsigned values
+const LONG: u16 = 4;
+
+/// Write `created` as `DateTimeOriginal` and `CreateDate` into the JPEG file at `path`
+///
+/// Only files without any exif data are changed, as editing existing exif data
+/// would require rewriting its offsets.
+/// Returns `false` if the file was left unchanged.
+pub(crate) fn backfill_date(path: &Path, created: &DateTime<FixedOffset>) -> Result<bool> {
+    let data = fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+    let mut jpeg = Jpeg::from_bytes(data.into())?;
+    if jpeg.exif().is_some() {
+        return Ok(false);
+    }
+
+    // The exif segment follows the JFIF segment if there is one
+    let segments = jpeg.segments_mut();
+    let position = usize::from(
+        segments
+            .first()
+            .is_some_and(|s| s.marker() == markers::APP0),
+    );
+    segments.insert(
+        position,
+        JpegSegment::new_with_contents(markers::APP1, exif_segment(created).into()),
+    );
+
+    let mut output = Vec::new();
+    jpeg.encoder().write_to(&mut output)?;
+    fs::write(path, output).with_context(|| format!("failed to write {}", path.display()))?;
+
+    Ok(true)
+}
+
+/// Number of bytes [backfill_date] adds to a file
+///
+/// The written values have a fixed length, so this is the same for every date.
+pub(crate) fn added_size() -> u64 {
+    // Marker and length of the segment
+    4 + exif_segment(&DateTime::default()).len() as u64
+}
+
+/// Contents of the exif segment holding `created`
+fn exif_segment(created: &DateTime<FixedOffset>) -> Vec<u8> {
+    let date = created.format("%Y:%m:%d %H:%M:%S").to_string();
+    let offset = created.format("%:z").to_string();
+    let mut app1 = b"Exif\0\0".to_vec();
+    app1.extend(tiff_with_tags(&[
+        (DATE_TIME_ORIGINAL, &date),
+        (CREATE_DATE, &date),
+        (OFFSET_TIME_ORIGINAL, &offset),
+        (OFFSET_TIME_DIGITIZED, &offset),
+    ]));
+    app1
+}
+
+/// Encode a little-endian TIFF structure with the text `tags` in its Exif IFD
+///
+/// IFD0 only points to the Exif IFD. The tags have to be sorted by their number.
+fn tiff_with_tags(tags: &[(u16, &str)]) -> Vec<u8> {
+    const IFD0: u32 = 8;
+    const EXIF_IFD: u32 = IFD0 + 2 + 12 + 4;
+
+    let mut tiff = b"II*\0".to_vec();
+    tiff.extend(IFD0.to_le_bytes());
+    tiff.extend(1u16.to_le_bytes());
+    tiff.extend(EXIF_IFD_POINTER.to_le_bytes());
+    tiff.extend(LONG.to_le_bytes());
+    tiff.extend(1u32.to_le_bytes());
+    tiff.extend(EXIF_IFD.to_le_bytes());
+    tiff.extend(0u32.to_le_bytes());
+
+    let mut offset = EXIF_IFD + 2 + 12 * tags.len() as u32 + 4;
+    let mut values = Vec::new();
+    tiff.extend((tags.len() as u16).to_le_bytes());
+    for (tag, text) in tags {
+        let mut value = text.as_bytes().to_vec();
+        value.push(0);
+        tiff.extend(tag.to_le_bytes());
+        tiff.extend(ASCII.to_le_bytes());
+        tiff.extend((value.len() as u32).to_le_bytes());
+        if value.len() <= 4 {
+            // Short values are stored in the entry itself
+            value.resize(4, 0);
+            tiff.extend(value);
+        } else {
+            tiff.extend(offset.to_le_bytes());
+            offset += value.len() as u32;
+            values.extend(value);
+        }
+    }
+    tiff.extend(0u32.to_le_bytes());
+    tiff.extend(values);
+    tiff
+}
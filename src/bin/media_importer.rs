@@ -1,13 +1,254 @@
+use std::{
+    collections::BTreeMap,
+    env,
+    fs::File,
+    io::{self, IsTerminal as _, Write, stderr, stdout},
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
 use anyhow::Result;
-use log::info;
-use media_tools::{ImportConfig, import_media_files};
+use clap::Parser;
+use figment::providers::Serialized;
+use indicatif::{ProgressBar, ProgressStyle};
+use media_tools::{
+    Duplicate, ImportConfig, ImportObserver, SkipReason, count_media_files,
+    import_media_files_with_observer,
+};
+use serde::Serialize;
+use tracing::{info, level_filters::LevelFilter, warn};
+use tracing_subscriber::EnvFilter;
+
+/// Import media files into date directories
+#[derive(Debug, Parser)]
+struct Args {
+    /// Path to the configuration file, read as YAML if it ends in `.yml` or `.yaml`
+    #[arg(long, default_value = "config.toml")]
+    config: String,
+
+    /// Log level, overriding `RUST_LOG`
+    #[arg(long)]
+    log_level: Option<LevelFilter>,
+
+    /// Only list the duplicates of existing files, to stdout or the given file,
+    /// without importing anything
+    #[arg(long, value_name = "FILE", num_args = 0..=1)]
+    list_duplicates: Option<Option<PathBuf>>,
+
+    /// Do not run the post-copy command of the configuration
+    #[arg(long)]
+    no_hooks: bool,
+
+    /// Number of threads to index and copy files with, overriding `index_threads`
+    /// and `copy_threads`
+    ///
+    /// With `--threads 1`, all work happens one file after another.
+    #[arg(long)]
+    threads: Option<usize>,
+
+    /// Only count the media files in the search paths and sum up their sizes,
+    /// without parsing or importing anything
+    #[arg(long)]
+    count_only: bool,
+
+    #[command(flatten)]
+    overrides: Overrides,
+}
+
+/// Configuration values taking precedence over the configuration file
+#[derive(Debug, clap::Args, Serialize)]
+struct Overrides {
+    /// Output path to import media files to
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    output_path: Option<String>,
+
+    /// Only log the planned changes without performing them
+    #[arg(long)]
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    dry_run: bool,
+
+    /// Only import files created at or after this date (RFC 3339 or YYYY-MM-DD)
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    since: Option<String>,
+
+    /// Delete existing lower-quality files replaced during the import
+    #[arg(long)]
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    prune_replaced: bool,
+
+    /// Abort on the first error instead of continuing with the next file
+    #[arg(long)]
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    strict: bool,
+
+    /// Stop after copying this many files, not counting skipped files
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    limit: Option<usize>,
+
+    /// Include the number of files copied to each date directory in the summary
+    #[arg(long)]
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    verbose_summary: bool,
+
+    /// Log the duration and throughput of the indexing, walking, exif parsing and copying
+    #[arg(long)]
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    profile: bool,
+
+    /// Overwrite files which appeared at a target after planning the copy
+    #[arg(long)]
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    force: bool,
+
+    /// Keep the modification and access time of the source files on their copies
+    #[arg(long = "copy-metadata")]
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    preserve_timestamps: bool,
+}
+
+/// Progress bar showing the processed files
+struct Progress {
+    bar: ProgressBar,
+    /// Set on Ctrl-C to stop the import after the current file
+    cancelled: Arc<AtomicBool>,
+}
+
+impl Progress {
+    /// Create a progress bar, hidden if stderr is not a terminal
+    fn new(cancelled: Arc<AtomicBool>) -> Self {
+        let bar = if stderr().is_terminal() {
+            ProgressBar::no_length()
+        } else {
+            ProgressBar::hidden()
+        };
+        bar.set_style(
+            ProgressStyle::with_template("{bar:40} {pos}/{len} files (ETA {eta})")
+                .expect("valid progress template"),
+        );
+        Self { bar, cancelled }
+    }
+}
+
+impl ImportObserver for Progress {
+    fn on_search_complete(&mut self, total: usize) {
+        self.bar.set_length(total as u64);
+    }
+
+    fn on_file_skipped(&mut self, _path: &Path, _reason: SkipReason) {
+        self.bar.inc(1);
+    }
+
+    fn on_file_copied(&mut self, _src: &Path, _dst: &Path) {
+        self.bar.inc(1);
+    }
+
+    fn on_file_failed(&mut self, _path: &Path, _error: &anyhow::Error) {
+        self.bar.inc(1);
+    }
+
+    fn cancel_flag(&self) -> Option<Arc<AtomicBool>> {
+        Some(self.cancelled.clone())
+    }
+}
 
 fn main() -> Result<()> {
-    env_logger::init();
+    let mut args = Args::parse();
+
+    let mut filter = match args.log_level {
+        Some(level) => EnvFilter::default().add_directive(level.into()),
+        None => EnvFilter::from_default_env(),
+    };
+    // The exif parser traces every parsed entry and warns about missing GPS info
+    if !env::var(EnvFilter::DEFAULT_ENV).is_ok_and(|v| v.contains("nom_exif")) {
+        filter = filter.add_directive("nom_exif=error".parse()?);
+    }
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(stderr)
+        .with_ansi(stderr().is_terminal())
+        .init();
     info!("Starting importer");
 
-    let config = ImportConfig::try_load("config.toml")?;
-    import_media_files(&config)?;
+    if args.list_duplicates.is_some() {
+        args.overrides.dry_run = true;
+    }
+
+    let mut config = ImportConfig::try_from_figment(
+        ImportConfig::figment_auto(&args.config).merge(Serialized::defaults(&args.overrides)),
+    )?;
+    if args.no_hooks {
+        config.post_copy_command = None;
+    }
+    if let Some(threads) = args.threads {
+        config.index_threads = Some(threads);
+        config.copy_threads = Some(threads);
+    }
+
+    if args.count_only {
+        println!("{}", count_media_files(&config)?);
+        return Ok(());
+    }
+
+    // Finish the current file and print the statistics on Ctrl-C instead of aborting,
+    // a second Ctrl-C aborts right away
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let flag = cancelled.clone();
+    ctrlc::set_handler(move || {
+        if flag.swap(true, Ordering::Relaxed) {
+            warn!("Interrupted again, aborting");
+            process::exit(130);
+        }
+        warn!("Interrupted, stopping after the current file");
+    })?;
+
+    let mut progress = Progress::new(cancelled);
+    let stats = import_media_files_with_observer(&config, &mut progress)?;
+    progress.bar.finish();
+
+    if stats.cancelled {
+        eprintln!(
+            "Cancelled: copied {}, skipped {}, {} errors",
+            stats.copied, stats.skipped, stats.errors
+        );
+    }
+
+    match args.list_duplicates {
+        Some(Some(path)) => write_duplicates(&stats.duplicates, File::create(path)?)?,
+        Some(None) => write_duplicates(&stats.duplicates, stdout().lock())?,
+        None => {}
+    }
 
     Ok(())
 }
+
+/// Write the `duplicates` grouped by the existing file they duplicate
+fn write_duplicates(duplicates: &[Duplicate], mut out: impl Write) -> io::Result<()> {
+    let mut groups: BTreeMap<&Path, Vec<&Duplicate>> = BTreeMap::new();
+    for duplicate in duplicates {
+        groups
+            .entry(&duplicate.existing)
+            .or_default()
+            .push(duplicate);
+    }
+
+    for (existing, duplicates) in groups {
+        writeln!(out, "{}", existing.display())?;
+        for duplicate in duplicates {
+            writeln!(
+                out,
+                "  {} ({:?})",
+                duplicate.source.display(),
+                duplicate.reason
+            )?;
+        }
+    }
+
+    out.flush()
+}
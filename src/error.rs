@@ -0,0 +1,48 @@
+//! Errors
+//!
+//! This module provides the error type of the public functions.
+//! Internally, errors are handled with `anyhow` and categorized when they are returned.
+
+use std::io;
+
+/// Error of the media tools, categorized by its cause
+///
+/// Each category keeps the full chain of context messages,
+/// which is printed with the alternate format `{:#}`.
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum MediaToolsError {
+    /// The configuration failed to load or has invalid values
+    #[error(transparent)]
+    Config(anyhow::Error),
+    /// A configured path does not exist or is not a directory
+    #[error(transparent)]
+    Path(anyhow::Error),
+    /// Reading or writing a file failed
+    #[error(transparent)]
+    Io(anyhow::Error),
+    /// The exif data or track info of a media file failed to parse
+    #[error(transparent)]
+    Exif(anyhow::Error),
+    /// Any other error
+    #[error(transparent)]
+    Other(anyhow::Error),
+}
+
+impl From<anyhow::Error> for MediaToolsError {
+    /// Categorize `error` by the first known cause in its chain
+    fn from(error: anyhow::Error) -> Self {
+        let error = match error.downcast::<Self>() {
+            Ok(error) => return error,
+            Err(error) => error,
+        };
+
+        if error.chain().any(|e| e.is::<nom_exif::Error>()) {
+            Self::Exif(error)
+        } else if error.chain().any(|e| e.is::<io::Error>()) {
+            Self::Io(error)
+        } else {
+            Self::Other(error)
+        }
+    }
+}
@@ -0,0 +1,57 @@
+//! Fallback to `exiftool`
+//!
+//! This module reads creation dates with the external `exiftool` program,
+//! which handles some files `nom_exif` fails to parse.
+
+use std::{path::Path, process::Command};
+
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, FixedOffset, Local, NaiveDateTime, TimeZone as _};
+use serde::Deserialize;
+
+/// Entry of the JSON output of `exiftool -j`
+#[derive(Deserialize)]
+struct Entry {
+    #[serde(rename = "CreateDate")]
+    create_date: Option<String>,
+}
+
+/// Read the `CreateDate` tag of the file at `path` with `exiftool`
+///
+/// Timestamps without an offset are interpreted in local time.
+/// Returns `Ok(None)` if the file has no such tag.
+pub(crate) fn create_date(path: &Path) -> Result<Option<DateTime<FixedOffset>>> {
+    let output = Command::new("exiftool")
+        .args(["-CreateDate", "-j"])
+        .arg(path)
+        .output()
+        .context("failed to run exiftool")?;
+    // exiftool exits with an error for files without any of the requested tags
+    if !output.status.success() && output.stdout.is_empty() {
+        bail!(
+            "exiftool failed with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    let entries: Vec<Entry> =
+        serde_json::from_slice(&output.stdout).context("failed to parse exiftool output")?;
+    let Some(date) = entries.into_iter().find_map(|e| e.create_date) else {
+        return Ok(None);
+    };
+    parse_date(&date).map(Some)
+}
+
+/// Parse an exif timestamp like `2024:03:15 10:00:00`, optionally followed by an offset
+fn parse_date(date: &str) -> Result<DateTime<FixedOffset>> {
+    if let Ok(created) = DateTime::parse_from_str(date, "%Y:%m:%d %H:%M:%S%:z") {
+        return Ok(created);
+    }
+
+    NaiveDateTime::parse_from_str(date, "%Y:%m:%d %H:%M:%S")
+        .ok()
+        .and_then(|d| Local.from_local_datetime(&d).earliest())
+        .map(|d| d.fixed_offset())
+        .with_context(|| format!("invalid exiftool date '{date}'"))
+}
@@ -5,227 +5,4301 @@
 //! and duplicates can be skipped.
 
 use std::{
+    borrow::Cow,
+    cell::{Cell, OnceCell, RefCell},
     collections::{HashMap, HashSet},
-    ffi::OsString,
-    fs,
-    hash::{DefaultHasher, Hash, Hasher as _},
-    path::{Path, PathBuf},
-    str::FromStr as _,
+    env,
+    ffi::{OsStr, OsString},
+    fmt::{self, Write as _},
+    fs::{self, File, FileTimes},
+    io::{self, Cursor, Read, Seek},
+    path::{Component, Path, PathBuf},
+    process::Command,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        mpsc,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime},
 };
 
-use anyhow::{Context, Result, bail};
-use chrono::{DateTime, Datelike as _, FixedOffset};
+use anyhow::{Context, Result, anyhow, bail};
+use chrono::{
+    DateTime, Datelike as _, FixedOffset, Local, NaiveDate, NaiveTime, TimeDelta, TimeZone as _,
+    Timelike as _, Weekday,
+    format::{Item, StrftimeItems},
+};
+use chrono_tz::Tz;
 use figment::{
-    Figment,
-    providers::{Format as _, Toml},
+    Figment, Provider,
+    providers::{Env, Format as _, Serialized, Toml, Yaml},
+    value::Value,
 };
-use log::{debug, warn};
+use globset::{Glob, GlobSet, GlobSetBuilder};
 use nom_exif::{
-    EntryValue, Exif, ExifIter, ExifTag, MediaParser, MediaSource, TrackInfo, TrackInfoTag,
+    EntryValue, Exif, ExifIter, ExifTag, LatLng, MediaParser, MediaSource, TrackInfo, TrackInfoTag,
 };
-use serde::Deserialize;
+use rayon::{ThreadPool, ThreadPoolBuilder, prelude::*};
+use serde::{Deserialize, Deserializer, Serialize, de};
+use sha2::{Digest as _, Sha256};
+use tracing::{debug, info, info_span, warn};
 use walkdir::WalkDir;
+use xxhash_rust::xxh3::Xxh3;
+
+#[cfg(feature = "zip")]
+use crate::archive::ZipMedia;
+use crate::{error::MediaToolsError, throttle::Throttle};
+
+/// Import configuration
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    pub extensions: Vec<String>,
+    pub existing_paths: Vec<String>,
+    pub search_paths: Vec<String>,
+    pub output_path: String,
+    /// Only log the planned filesystem changes without performing them
+    #[serde(default)]
+    pub dry_run: bool,
+    /// Detect duplicates by content hash if no file with the same name exists
+    #[serde(default)]
+    pub dedup_by_content: bool,
+    /// strftime-style format of the date directories, e.g. `%Y/%B`
+    ///
+    /// Defaults to `%Y_%m_%d`.
+    pub date_format: Option<String>,
+    /// Depth of the date directories, as an alternative to `date_format`
+    ///
+    /// Defaults to `day`.
+    pub bucket_depth: Option<BucketDepth>,
+    /// Move files instead of copying them, removing the source after the transfer
+    #[serde(default)]
+    pub move_files: bool,
+    /// Clear the read-only attribute of moved files which cannot be removed otherwise,
+    /// e.g. on Windows, instead of leaving them in place
+    #[serde(default)]
+    pub force_move_readonly: bool,
+    /// Link files into the output path instead of copying them, if both are on the same device
+    ///
+    /// Files on other devices are copied.
+    /// Linked files share their content with the source,
+    /// so this cannot be combined with moving, rotating or backfilling files.
+    pub link_mode: Option<LinkMode>,
+    /// Maximum number of threads used to index existing files
+    ///
+    /// Defaults to the number of CPUs.
+    pub index_threads: Option<usize>,
+    /// Extensions of raw files, e.g. `CR2` or `NEF`
+    ///
+    /// Other media files with the same stem as a raw file in the same directory,
+    /// e.g. the JPEG of a RAW+JPEG shot,
+    /// are imported into the date directory of the raw file.
+    #[serde(default)]
+    pub raw_extensions: Vec<String>,
+    /// Path of a file to cache the index of existing files in
+    ///
+    /// Only files with a changed modification time are parsed again on the next run,
+    /// their content and perceptual hashes are cached as well.
+    /// The cache is discarded when the date settings it was written with changed,
+    /// i.e. `date_tags`, `video_utc_offset`, `date_strategy` or `subsecond_precision`.
+    pub index_cache: Option<String>,
+    /// Path to write a JSON report of the actions taken for each media file to
+    pub report_path: Option<String>,
+    /// Path to write a JSON report of ambiguous files to
+    ///
+    /// A file is ambiguous if an existing file has the same name
+    /// and was created on the same day, but at a different time.
+    /// Such files are imported as distinct media files.
+    pub ambiguous_report_path: Option<String>,
+    /// Glob patterns of files and directories to exclude, e.g. `@eaDir` or `**/.thumbnails`
+    ///
+    /// Patterns are matched against both the full path and the file name.
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    /// Verify each copy by comparing its size and content hash to the source
+    #[serde(default)]
+    pub verify_copies: bool,
+    /// Set the modification and access time of each copied file to the times of its source
+    ///
+    /// Files extracted from archives only get the modification time of their entry.
+    /// Moved files keep their times anyway.
+    #[serde(default)]
+    pub preserve_timestamps: bool,
+    /// Map from extensions to the name of the tag holding the creation date,
+    /// e.g. `mov = "ComAppleQuicktimeCreationDate"`
+    ///
+    /// The tag is consulted before the default tags.
+    #[serde(default)]
+    pub date_tags: HashMap<String, DateTag>,
+    /// IANA timezone, e.g. `Europe/Berlin`, to convert timestamps to before bucketing by date
+    pub bucket_timezone: Option<Tz>,
+    /// Copy all files directly into the output path instead of date directories
+    #[serde(default)]
+    pub flatten: bool,
+    /// Place files with GPS coordinates in a geohash subdirectory of the date directory
+    /// with this many characters, e.g. 4 for cells of about 39 km
+    pub geohash_precision: Option<usize>,
+    /// Follow symbolic links when walking the existing and search paths
+    #[serde(default)]
+    pub follow_symlinks: bool,
+    /// Only import files created at or after this date,
+    /// given as RFC 3339 timestamp or `YYYY-MM-DD` in local time
+    #[serde(default, deserialize_with = "deserialize_since")]
+    pub since: Option<DateTime<FixedOffset>>,
+    /// Only import files created at or after the latest file in the output paths
+    ///
+    /// The output paths are indexed for this even without `index_output`.
+    /// Files without exif date in the output are not taken into account,
+    /// nor dates further in the future than `max_future_skew_secs`.
+    /// A later `since` takes precedence.
+    #[serde(default)]
+    pub auto_since: bool,
+    /// Only import files created within this time of day, e.g. `["22:00", "06:00"]`
+    ///
+    /// The start is inclusive and the end exclusive, a start after the end wraps past midnight.
+    /// Times are given as `HH:MM` or `HH:MM:SS` in the timezone of the creation timestamp,
+    /// or in the `bucket_timezone` if configured. Undated files are skipped.
+    #[serde(default, deserialize_with = "deserialize_time_range")]
+    pub time_range: Option<(NaiveTime, NaiveTime)>,
+    /// Only import files created on these weekdays, e.g. `["Sat", "Sun"]`
+    ///
+    /// Like the `time_range`, weekdays are taken in the timezone of the creation timestamp,
+    /// or in the `bucket_timezone` if configured. Undated files are skipped.
+    /// Files of all weekdays are imported if empty.
+    #[serde(default)]
+    pub weekdays: Vec<Weekday>,
+    /// Delete existing lower-quality files after importing a higher-quality version
+    #[serde(default)]
+    pub prune_replaced: bool,
+    /// Abort the import on the first error instead of continuing with the next file
+    #[serde(default)]
+    pub strict: bool,
+    /// Rotate copied JPEG files according to their exif orientation and reset the orientation
+    ///
+    /// The rotation is lossless and applied while writing the copy. Files that cannot be
+    /// rotated without loss, e.g. progressive JPEG files, or fail to parse keep their orientation.
+    /// Requires the `auto-rotate` feature.
+    #[serde(default)]
+    pub auto_rotate: bool,
+    /// Write the resolved creation date into copied JPEG files without exif data,
+    /// e.g. if they were dated by their modification time
+    ///
+    /// Only the copies are changed, never the source files.
+    /// Requires the `backfill-exif` feature.
+    #[serde(default)]
+    pub backfill_exif: bool,
+    /// Output paths for media files with specific extensions, e.g. to separate videos
+    ///
+    /// Files matching none of the routes are imported to `output_path`.
+    #[serde(default)]
+    pub output_routes: Vec<OutputRoute>,
+    /// Index the output paths as existing files, in addition to `existing_paths`
+    ///
+    /// Files imported by earlier runs into the date directories are then recognized
+    /// as duplicates even if the output is not one of the `existing_paths`.
+    /// Defaults to `true`, disable it to skip scanning a large output.
+    #[serde(default = "default_index_output")]
+    pub index_output: bool,
+    /// Append the content hash of each copied file to a manifest in `output_path`
+    ///
+    /// The manifest is named after the hash algorithm, e.g. `manifest.sha256`.
+    /// It uses the coreutils format and can be checked with `sha256sum -c` or `b3sum -c`.
+    #[serde(default)]
+    pub write_manifest: bool,
+    /// Detect the type of files without extension from their content
+    ///
+    /// Such files are included if the extension of the detected type, e.g. `jpg` or `mp4`,
+    /// is one of `extensions`.
+    #[serde(default)]
+    pub match_extensionless: bool,
+    /// Template of the target file names, e.g. `{date}_{time}_{orig}`
+    ///
+    /// `{date}` is replaced by the creation date as `YYYY-MM-DD`,
+    /// `{time}` by the creation time as `HHMMSS`
+    /// and `{orig}` by the original file name.
+    /// Defaults to the original file name.
+    /// Renamed files are only recognized as existing in later runs with `dedup_by_content`.
+    pub filename_template: Option<String>,
+    /// Number of threads to copy files with
+    ///
+    /// Without this setting, each file is copied right after deciding to import it.
+    pub copy_threads: Option<usize>,
+    /// Offset from UTC in minutes, e.g. `120` for UTC+2, to convert video timestamps to
+    ///
+    /// Many cameras store the creation date of videos in UTC,
+    /// which may place them in the wrong date directory.
+    /// Only video timestamps in UTC are converted,
+    /// timestamps with an offset like `com.apple.quicktime.creationdate` are kept.
+    pub video_utc_offset: Option<i32>,
+    /// Mirror the directory structure of the search paths in the output path
+    /// instead of sorting files into date directories
+    #[serde(default)]
+    pub preserve_structure: bool,
+    /// Number of times to retry a copy failing with a transient error
+    ///
+    /// Retries are delayed with exponential backoff starting at 100 ms.
+    /// Interrupted and would-block errors are transient.
+    #[serde(default)]
+    pub copy_retries: u32,
+    /// Raw OS error codes to treat as transient in addition, e.g. `11` for `EAGAIN`
+    #[serde(default)]
+    pub retry_os_errors: Vec<i32>,
+    /// Extensions of sidecar files, e.g. `xmp` or `aae`, to transfer along with media files
+    ///
+    /// Sidecars next to a media file named like `IMG_0001.xmp` or `IMG_0001.CR2.xmp`
+    /// are placed next to the imported file, following its new name.
+    /// Sidecars are never imported on their own.
+    #[serde(default)]
+    pub include_sidecars: Vec<String>,
+    /// Minimum size in bytes of media files to import, skipping e.g. empty stubs
+    ///
+    /// Defaults to 1 byte.
+    #[serde(default = "default_min_file_size")]
+    pub min_file_size: u64,
+    /// Minimum size in bytes of media files with specific extensions, e.g. `{ jpg = 100000 }`
+    ///
+    /// Takes precedence over `min_file_size` for these extensions,
+    /// e.g. to skip small thumbnails while still importing small icons of other types.
+    #[serde(default)]
+    pub min_size: HashMap<String, u64>,
+    /// Maximum seconds between consecutive shots of a burst
+    ///
+    /// Files with the same name prefix, e.g. `IMG_` of `IMG_0001.jpg`, created within this
+    /// window of each other are placed in a `burst_HHMMSS` subdirectory of the date directory.
+    pub burst_window_secs: Option<u64>,
+    /// Hamming distance of perceptual hashes below which images are near-duplicates, e.g. `6`
+    ///
+    /// Images are compared to all existing images by a 64-bit difference hash,
+    /// which detects e.g. re-exported JPEGs with different content.
+    /// Requires the `perceptual-hash` feature.
+    pub perceptual_hash_threshold: Option<u32>,
+    /// Extensions of images to compare by perceptual hash
+    ///
+    /// Defaults to `jpg` and `jpeg`.
+    #[serde(default = "default_perceptual_hash_extensions")]
+    pub perceptual_hash_extensions: Vec<String>,
+    /// Algorithm to hash file contents with, `sha256`, `blake3` or `xxh3`
+    ///
+    /// Used for deduplication by content, verification and the manifest.
+    /// Defaults to `sha256`.
+    #[serde(default)]
+    pub content_hash_algo: HashAlgorithm,
+    /// Include the number of files copied to each date directory in the summary
+    #[serde(default)]
+    pub verbose_summary: bool,
+    /// Log the duration and throughput of the import phases in the summary
+    #[serde(default)]
+    pub profile: bool,
+    /// Maximum rate in bytes per second to read files with while copying them
+    ///
+    /// Limits the combined rate of all copy threads,
+    /// e.g. to avoid saturating the link to a network share.
+    /// Reading metadata and hashing files is not limited.
+    pub max_read_bytes_per_sec: Option<u64>,
+    /// Skip files with the same content as any existing file before comparing names and dates
+    ///
+    /// Catches duplicates under different names and folder layouts.
+    /// This hashes every existing file and every candidate in full,
+    /// which reads the whole archive on each run and is slow on large or remote archives.
+    /// Consider `index_cache` to at least avoid re-parsing unchanged files.
+    #[serde(default)]
+    pub strict_content_dedup: bool,
+    /// Shell command to run after each copied file, e.g. `dam-add {path}`
+    ///
+    /// The command is split into words at whitespace, where single or double quotes
+    /// group words with whitespace, and run without a shell.
+    /// `{path}` is replaced by the path of the copied file within the words,
+    /// so that the path is passed as is, whatever characters it contains.
+    /// For shell features, run a shell explicitly, e.g. `sh -c 'dam-add "$0"' {path}`.
+    /// Commands run one after another, a non-zero exit code is only logged.
+    pub post_copy_command: Option<String>,
+    /// Name of the directory in the output path for files without a creation date
+    ///
+    /// Files without exif date and modification time are placed here
+    /// instead of the date directory of the Unix epoch.
+    /// Defaults to `_undated`.
+    pub undated_dir_name: Option<String>,
+    /// Strategy to determine the creation timestamp of media files from exif data and mtime
+    ///
+    /// Defaults to `exif-then-mtime`.
+    #[serde(default)]
+    pub date_strategy: DateStrategy,
+    /// Compare creation timestamps including fractions of a second
+    ///
+    /// By default, files created within the same second count as created at the same time,
+    /// e.g. to identify versions of a shot. With this option, the `SubSecTimeOriginal`,
+    /// `SubSecTimeDigitized` and `SubSecTime` tags are added to the exif dates,
+    /// so that rapid-fire shots of the same second are told apart.
+    #[serde(default)]
+    pub subsecond_precision: bool,
+    /// Maximum seconds a creation timestamp may lie in the future
+    ///
+    /// Later timestamps, e.g. corrupted exif dates in 2099, are ignored with a warning
+    /// and the next source of the `date_strategy` is used, the undated directory at last.
+    /// Defaults to one day.
+    #[serde(default = "default_max_future_skew_secs")]
+    pub max_future_skew_secs: u64,
+    /// Import the video of a Live Photo into the date directory of its image
+    ///
+    /// Live Photos are HEIC images with a MOV video of the same stem in the same directory.
+    /// Both are imported as separate files, dated by the image.
+    #[serde(default)]
+    pub pair_live_photos: bool,
+    /// Heuristic to decide which of two versions of a media file has the higher quality
+    ///
+    /// Versions have the same name and creation date.
+    /// Defaults to `larger-size`.
+    #[serde(default)]
+    pub quality_heuristic: QualityHeuristic,
+    /// Strategy to name files whose target is taken by a different file
+    ///
+    /// Defaults to `counter`.
+    #[serde(default)]
+    pub collision_strategy: CollisionStrategy,
+    /// Compare the content of files with the same name and size before skipping them
+    ///
+    /// Without this setting, such files are duplicates by name and size alone.
+    /// Files with different content are imported as distinct files.
+    #[serde(default)]
+    pub confirm_by_content: bool,
+    /// Maximum number of files to copy in one run, e.g. for a trial run
+    ///
+    /// Skipped files and failed copies do not count towards the limit.
+    pub limit: Option<usize>,
+    /// Read the `CreateDate` tag with `exiftool` for files without a date parsed by `nom_exif`
+    ///
+    /// Rescues files `nom_exif` fails to parse at the cost of running `exiftool` for each of them.
+    /// Requires the `exiftool` feature and `exiftool` in the `PATH`.
+    #[serde(default)]
+    pub exiftool_fallback: bool,
+    /// Overwrite a differing file which appeared at a target after planning the copy
+    ///
+    /// Targets are chosen next to existing files, but another process may write to the output
+    /// until the file is copied, especially while planning all copies with `copy_threads`.
+    /// Such files are kept and the source is skipped with a warning by default.
+    #[serde(default)]
+    pub force: bool,
+}
+
+/// Heuristic to decide which of two versions of a media file has the higher quality
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum QualityHeuristic {
+    /// The larger file has the higher quality
+    #[default]
+    LargerSize,
+    /// The file modified more recently has the higher quality, e.g. after re-encoding
+    NewerMtime,
+    /// Extensions earlier in the list have the higher quality, e.g. `["mov", "mp4"]`
+    ///
+    /// Configured as `quality_heuristic = { prefer-extension = ["mov", "mp4"] }`.
+    /// Versions with the same stem and another extension of the list are compared as well.
+    /// Files with unranked extensions have the lowest quality,
+    /// ties are decided by size.
+    PreferExtension(Vec<String>),
+}
+
+/// Kind of link to create instead of copying a media file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LinkMode {
+    /// Hard link sharing the content with the source
+    Hard,
+    /// Symbolic link to the canonical path of the source
+    Sym,
+}
+
+/// Strategy to name a media file whose target is taken by a different file
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CollisionStrategy {
+    /// Append the first free number to the file stem, e.g. `IMG_1234_1.jpg`
+    ///
+    /// The numbers depend on the order in which files are found.
+    #[default]
+    Counter,
+    /// Append the first 8 hex digits of the BLAKE3 hash of the content, e.g. `IMG_1234_af1349b9.jpg`
+    ///
+    /// The same file always gets the same name, so that repeated imports find it.
+    HashSuffix,
+}
+
+/// Strategy to determine the creation timestamp of a media file
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DateStrategy {
+    /// Only use the exif creation timestamp
+    ExifOnly,
+    /// Only use the filesystem modification time
+    MtimeOnly,
+    /// Use the exif creation timestamp, or the modification time for files without
+    #[default]
+    ExifThenMtime,
+    /// Use the earlier of the exif creation timestamp and the modification time
+    ///
+    /// This keeps the capture date of files modified later, e.g. by editing.
+    Earliest,
+}
+
+/// Depth of the date directories media files are imported to
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BucketDepth {
+    /// One directory per day, e.g. `2024_03_15`
+    #[default]
+    Day,
+    /// One directory per month, nested in one per year, e.g. `2024/2024-03`
+    Month,
+    /// One directory per year, e.g. `2024`
+    Year,
+}
+
+impl BucketDepth {
+    /// strftime-style format of the date directories
+    fn date_format(self) -> &'static str {
+        match self {
+            Self::Day => "%Y_%m_%d",
+            Self::Month => "%Y/%Y-%m",
+            Self::Year => "%Y",
+        }
+    }
+}
+
+/// Prefix of environment variables holding configuration values
+const ENV_PREFIX: &str = "MEDIA_";
+
+/// Configuration keys holding lists, which are separated by commas in environment variables
+const ENV_LIST_KEYS: [&str; 10] = [
+    "extensions",
+    "existing_paths",
+    "search_paths",
+    "raw_extensions",
+    "exclude",
+    "retry_os_errors",
+    "include_sidecars",
+    "perceptual_hash_extensions",
+    "time_range",
+    "weekdays",
+];
+
+/// Default extensions of images to compare by perceptual hash
+fn default_perceptual_hash_extensions() -> Vec<String> {
+    vec!["jpg".to_owned(), "jpeg".to_owned()]
+}
+
+/// Default to indexing the output paths, recognizing files imported by earlier runs
+fn default_index_output() -> bool {
+    true
+}
+
+/// Default maximum seconds a creation timestamp may lie in the future, one day
+fn default_max_future_skew_secs() -> u64 {
+    24 * 60 * 60
+}
+
+/// Default minimum size of media files, skipping only empty files
+fn default_min_file_size() -> u64 {
+    1
+}
+
+/// Output path for media files with specific extensions
+#[derive(Debug, Clone, Deserialize)]
+pub struct OutputRoute {
+    /// Extensions of the media files to import to `path`
+    pub extensions: Vec<String>,
+    /// Output path for these media files
+    pub path: String,
+}
+
+/// Deserialize an optional RFC 3339 timestamp or `YYYY-MM-DD` date
+fn deserialize_since<'de, D>(deserializer: D) -> Result<Option<DateTime<FixedOffset>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let Some(since) = Option::<String>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+
+    parse_date(&since).map(Some).map_err(de::Error::custom)
+}
+
+/// Deserialize an optional pair of `HH:MM` or `HH:MM:SS` times of day
+fn deserialize_time_range<'de, D>(
+    deserializer: D,
+) -> Result<Option<(NaiveTime, NaiveTime)>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let Some((start, end)) = Option::<(String, String)>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+
+    let parse = |time: &str| {
+        NaiveTime::parse_from_str(time, "%H:%M:%S")
+            .or_else(|_| NaiveTime::parse_from_str(time, "%H:%M"))
+            .map_err(|_| de::Error::custom(format!("invalid time '{time}', expected HH:MM")))
+    };
+    Ok(Some((parse(&start)?, parse(&end)?)))
+}
+
+/// Check if the time of day of `timestamp` is within `range`, see [Config::time_range]
+fn in_time_range(
+    timestamp: &DateTime<FixedOffset>,
+    (start, end): (NaiveTime, NaiveTime),
+    timezone: Option<Tz>,
+) -> bool {
+    let time = match timezone {
+        Some(timezone) => timestamp.with_timezone(&timezone).time(),
+        None => timestamp.time(),
+    };
+    if start <= end {
+        start <= time && time < end
+    } else {
+        start <= time || time < end
+    }
+}
+
+/// Check if `timestamp` is on one of the `weekdays`, see [Config::weekdays]
+fn on_weekdays(
+    timestamp: &DateTime<FixedOffset>,
+    weekdays: &[Weekday],
+    timezone: Option<Tz>,
+) -> bool {
+    let weekday = match timezone {
+        Some(timezone) => timestamp.with_timezone(&timezone).weekday(),
+        None => timestamp.weekday(),
+    };
+    weekdays.contains(&weekday)
+}
+
+/// Parse an RFC 3339 timestamp or a `YYYY-MM-DD` date at midnight in local time
+fn parse_date(date: &str) -> Result<DateTime<FixedOffset>> {
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(date) {
+        return Ok(timestamp);
+    }
+
+    NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .ok()
+        .and_then(|d| {
+            Local
+                .from_local_datetime(&d.and_time(NaiveTime::MIN))
+                .earliest()
+        })
+        .map(|d| d.fixed_offset())
+        .with_context(|| format!("invalid date '{date}', expected RFC 3339 or YYYY-MM-DD"))
+}
+
+/// Exif or track info tag holding a creation date
+///
+/// Parsed from the tag name as printed by `nom_exif`, e.g. `DateTimeOriginal`.
+/// Names existing for both images and videos, like `CreateDate`, apply to both.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct DateTag {
+    exif: Option<ExifTag>,
+    track: Option<TrackInfoTag>,
+}
+
+impl TryFrom<String> for DateTag {
+    type Error = anyhow::Error;
+
+    fn try_from(name: String) -> Result<Self> {
+        const TRACK_TAGS: [TrackInfoTag; 1] = [TrackInfoTag::CreateDate];
+
+        let exif = (0..=u16::MAX)
+            .filter_map(|code| ExifTag::try_from(code).ok())
+            .find(|tag| tag.to_string() == name);
+        let track = match name.as_str() {
+            "ComAppleQuicktimeCreationDate" | "com.apple.quicktime.creationdate" => {
+                Some(TrackInfoTag::CreateDate)
+            }
+            _ => TRACK_TAGS.into_iter().find(|tag| tag.to_string() == name),
+        };
+
+        if exif.is_none() && track.is_none() {
+            bail!("unknown date tag '{name}'");
+        }
+
+        Ok(Self { exif, track })
+    }
+}
+
+impl Config {
+    /// Try loading the configuration from a toml file and the environment
+    pub fn try_load(toml: &str) -> Result<Self, MediaToolsError> {
+        Self::try_from_figment(Self::figment(toml))
+    }
+
+    /// Try loading the configuration from a YAML or TOML file and the environment
+    ///
+    /// See [Config::figment_auto] for how the format is chosen.
+    pub fn try_load_auto(path: &str) -> Result<Self, MediaToolsError> {
+        Self::try_from_figment(Self::figment_auto(path))
+    }
+
+    /// Build a [Figment] reading the configuration from a toml file and the environment
+    ///
+    /// Environment variables prefixed with `MEDIA_`, e.g. `MEDIA_OUTPUT_PATH`,
+    /// take precedence over values of the file.
+    /// Lists like `MEDIA_EXTENSIONS=jpg,mp4` are separated by commas,
+    /// entries of tables like `MEDIA_MIN_SIZE__JPG=100000` are separated by `__`.
+    /// The file is optional if all required values are set in the environment.
+    ///
+    /// Further providers can be merged before extracting the configuration
+    /// with [Config::try_from_figment].
+    pub fn figment(toml: &str) -> Figment {
+        Self::figment_from(Toml::file(toml))
+    }
+
+    /// Build a [Figment] like [Config::figment], choosing the format by the file extension
+    ///
+    /// Files ending in `.yml` or `.yaml` are read as YAML, all others as TOML.
+    pub fn figment_auto(path: &str) -> Figment {
+        let yaml = Path::new(path)
+            .extension()
+            .and_then(OsStr::to_str)
+            .is_some_and(|e| e.eq_ignore_ascii_case("yml") || e.eq_ignore_ascii_case("yaml"));
+        if yaml {
+            Self::figment_from(Yaml::file(path))
+        } else {
+            Self::figment(path)
+        }
+    }
+
+    /// Build a [Figment] reading the configuration from `file` and the environment
+    fn figment_from(file: impl Provider) -> Figment {
+        let lists: HashMap<&str, Vec<Value>> = ENV_LIST_KEYS
+            .iter()
+            .filter_map(|&key| {
+                let value = env::var(format!("{ENV_PREFIX}{}", key.to_uppercase())).ok()?;
+                let items = value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|item| !item.is_empty())
+                    .map(|item| item.parse().unwrap_or_else(|e| match e {}))
+                    .collect();
+                Some((key, items))
+            })
+            .collect();
+
+        Figment::new()
+            .merge(file)
+            .merge(Env::prefixed(ENV_PREFIX).split("__").ignore(&ENV_LIST_KEYS))
+            .merge(Serialized::defaults(lists))
+    }
+
+    /// Try extracting and validating the configuration from a [Figment]
+    pub fn try_from_figment(figment: Figment) -> Result<Self, MediaToolsError> {
+        let config: Self = figment
+            .extract()
+            .map_err(|e| MediaToolsError::Config(e.into()))?;
+        config.check().map_err(MediaToolsError::Config)?;
+        Ok(config)
+    }
+
+    /// Check the values of the configuration which do not depend on the filesystem
+    fn check(&self) -> Result<()> {
+        let config = self;
+        if let Some(date_format) = &config.date_format {
+            check_date_format(date_format)?;
+        }
+        if config.date_format.is_some() && config.bucket_depth.is_some() {
+            bail!("date_format and bucket_depth are mutually exclusive");
+        }
+        if let Some(template) = &config.filename_template {
+            check_filename_template(template)?;
+        }
+        if let Some(command) = &config.post_copy_command
+            && command_words(command)?.is_empty()
+        {
+            bail!("post_copy_command must not be empty");
+        }
+        if let Some(offset) = config.video_utc_offset {
+            config
+                .video_offset()
+                .with_context(|| format!("video_utc_offset of {offset} minutes is out of range"))?;
+        }
+
+        if config.output_path.is_empty() {
+            bail!("output_path must not be empty");
+        }
+        for route in &config.output_routes {
+            if route.path.is_empty() || route.extensions.is_empty() {
+                bail!("output routes need a path and at least one extension");
+            }
+        }
+
+        if let Some(name) = &config.undated_dir_name
+            && !matches!(
+                Path::new(name).components().collect::<Vec<_>>()[..],
+                [Component::Normal(_)]
+            )
+        {
+            bail!("undated_dir_name '{name}' must be a single directory name");
+        }
+
+        if config.flatten && config.preserve_structure {
+            bail!("flatten and preserve_structure are mutually exclusive");
+        }
+
+        if config.auto_rotate && !cfg!(feature = "auto-rotate") {
+            bail!("auto_rotate requires the 'auto-rotate' feature");
+        }
+        if config.backfill_exif && !cfg!(feature = "backfill-exif") {
+            bail!("backfill_exif requires the 'backfill-exif' feature");
+        }
+        if config.link_mode.is_some()
+            && (config.move_files || config.auto_rotate || config.backfill_exif)
+        {
+            bail!("link_mode cannot be combined with move_files, auto_rotate or backfill_exif");
+        }
+        if config.perceptual_hash_threshold.is_some() && !cfg!(feature = "perceptual-hash") {
+            bail!("perceptual_hash_threshold requires the 'perceptual-hash' feature");
+        }
+        if config.exiftool_fallback && !cfg!(feature = "exiftool") {
+            bail!("exiftool_fallback requires the 'exiftool' feature");
+        }
+
+        Ok(())
+    }
+
+    /// Check that the existing and search paths are directories
+    ///
+    /// The output paths may not exist yet, but must be directories if they do.
+    pub fn validate(&self) -> Result<(), MediaToolsError> {
+        self.check_paths().map_err(MediaToolsError::Path)
+    }
+
+    /// See [Config::validate]
+    fn check_paths(&self) -> Result<()> {
+        for path in &self.existing_paths {
+            if !Path::new(path).is_dir() {
+                bail!("existing path '{path}' does not exist or is not a directory");
+            }
+        }
+        for path in &self.search_paths {
+            if is_zip_archive(Path::new(path)) {
+                if !cfg!(feature = "zip") {
+                    bail!(
+                        "search path '{path}' is a zip archive, which requires the 'zip' feature"
+                    );
+                }
+            } else if !Path::new(path).is_dir() {
+                bail!("search path '{path}' does not exist or is not a directory");
+            }
+        }
+
+        let outputs = self.output_routes.iter().map(|r| &r.path);
+        for path in std::iter::once(&self.output_path).chain(outputs) {
+            let path = Path::new(path);
+            if path.exists() && !path.is_dir() {
+                bail!("output path '{}' is not a directory", path.display());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Offset to convert video timestamps in UTC to
+    fn video_offset(&self) -> Option<FixedOffset> {
+        self.video_utc_offset
+            .and_then(|minutes| FixedOffset::east_opt(minutes.checked_mul(60)?))
+    }
+
+    /// Output path for the media file at `path`, chosen by its extension
+    fn output_root(&self, path: &Path) -> &Path {
+        let route = path.extension().and_then(|ext| {
+            self.output_routes.iter().find(|r| {
+                r.extensions
+                    .iter()
+                    .any(|e| ext.eq_ignore_ascii_case(trim_dot(e)))
+            })
+        });
+
+        Path::new(route.map_or(&self.output_path, |r| &r.path))
+    }
+
+    /// Minimum size in bytes of the media file at `path`, chosen by its extension
+    fn min_size(&self, path: &Path) -> u64 {
+        path.extension()
+            .and_then(|ext| {
+                self.min_size
+                    .iter()
+                    .find(|(e, _)| ext.eq_ignore_ascii_case(trim_dot(e)))
+            })
+            .map_or(self.min_file_size, |(_, &size)| size)
+    }
+
+    /// Canonical paths of the existing output paths, including those of the output routes
+    fn canonical_outputs(&self) -> Vec<PathBuf> {
+        std::iter::once(&self.output_path)
+            .chain(self.output_routes.iter().map(|r| &r.path))
+            .filter_map(|path| fs::canonicalize(path).ok())
+            .collect()
+    }
+}
+
+/// Import media files according to the [Config]
+pub fn import_media_files(config: &Config) -> Result<Statistics, MediaToolsError> {
+    import_media_files_with_observer(config, &mut ())
+}
+
+/// Import media files according to the [Config] and return the target paths of copied files
+///
+/// In a dry run, the planned target paths are returned.
+pub fn import_media_files_reporting(config: &Config) -> Result<Vec<PathBuf>, MediaToolsError> {
+    let mut targets = CopiedTargets::default();
+    import_media_files_with_observer(config, &mut targets)?;
+    Ok(targets.0)
+}
+
+/// Import media files according to the [Config] and report progress to `observer`
+pub fn import_media_files_with_observer(
+    config: &Config,
+    observer: &mut impl ImportObserver,
+) -> Result<Statistics, MediaToolsError> {
+    config.validate()?;
+    import(config, observer).map_err(Into::into)
+}
+
+/// Import media files according to the validated [Config], see [import_media_files_with_observer]
+fn import(config: &Config, observer: &mut impl ImportObserver) -> Result<Statistics> {
+    let raw_extensions = ExtensionSet::new(&config.raw_extensions)?;
+    let walk = MediaWalk::new(config)?;
+
+    let exif_nanos = EXIF_NANOS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    let cancel_flag = observer.cancel_flag();
+    let (existing, config) = index_existing(config, &walk, cancel_flag.as_deref())?;
+    let config = &*config;
+    let index_time = start.elapsed();
+
+    // Importing with a partial index would copy duplicates of the files not indexed
+    if cancel_flag.is_some_and(|c| c.load(Ordering::Relaxed)) {
+        let stats = Statistics {
+            cancelled: true,
+            ..Default::default()
+        };
+        stats.log_summary(config);
+        return Ok(stats);
+    }
+
+    // Synchronize files from search paths
+    let mut stats = sync_media_files(&existing, &walk, &raw_extensions, config, observer)?;
+    stats.parse_failed = existing.failed.len();
+    stats.indexed = existing.name_map.values().map(Vec::len).sum();
+    stats.index_parsed = existing.parsed;
+    stats.timings.index = index_time;
+    stats.timings.exif =
+        Duration::from_nanos(EXIF_NANOS.load(Ordering::Relaxed).wrapping_sub(exif_nanos));
+    walk.warn_unmatched_extensions();
+
+    if config.prune_replaced && !config.dry_run {
+        prune_replaced(&stats.replaced);
+    }
+
+    stats.log_summary(config);
+
+    Ok(stats)
+}
+
+/// Delete the `replaced` lower-quality versions of imported files
+///
+/// Failures to delete a file are only logged.
+fn prune_replaced(replaced: &[PathBuf]) {
+    for path in replaced {
+        match fs::remove_file(path) {
+            Ok(()) => info!("Deleted replaced lower-quality file {}", path.display()),
+            Err(e) => warn!("Failed to delete replaced file {}: {e}", path.display()),
+        }
+    }
+}
+
+/// Format `bytes` with a binary unit, e.g. `4.2 GiB`
+fn human_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["KiB", "MiB", "GiB", "TiB", "PiB"];
+
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = UNITS[0];
+    for next in &UNITS[1..] {
+        if value < 1024.0 {
+            break;
+        }
+        value /= 1024.0;
+        unit = next;
+    }
+
+    format!("{value:.1} {unit}")
+}
+
+/// Observer of the progress of an import
+///
+/// All methods default to doing nothing.
+pub trait ImportObserver {
+    /// The search paths were crawled and `total` media files will be processed
+    fn on_search_complete(&mut self, _total: usize) {}
+
+    /// A media file was found in the search paths
+    fn on_file_found(&mut self, _path: &Path) {}
+
+    /// A media file was skipped
+    fn on_file_skipped(&mut self, _path: &Path, _reason: SkipReason) {}
+
+    /// A media file was copied (or moved) from `src` to `dst`
+    ///
+    /// In a dry run, this reports the planned copy.
+    fn on_file_copied(&mut self, _src: &Path, _dst: &Path) {}
+
+    /// A media file failed to import
+    fn on_file_failed(&mut self, _path: &Path, _error: &anyhow::Error) {}
+
+    /// Flag which, once set, stops the import after the files currently being imported
+    ///
+    /// The files processed so far are reported in the [Statistics] as usual.
+    /// If set while indexing the existing files, nothing is imported.
+    fn cancel_flag(&self) -> Option<Arc<AtomicBool>> {
+        None
+    }
+}
+
+/// No-op observer
+impl ImportObserver for () {}
+
+/// Observer collecting the target paths of copied files
+#[derive(Default)]
+struct CopiedTargets(Vec<PathBuf>);
+
+impl ImportObserver for CopiedTargets {
+    fn on_file_copied(&mut self, _src: &Path, dst: &Path) {
+        self.0.push(dst.to_owned());
+    }
+}
+
+/// Reason for skipping a media file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SkipReason {
+    /// An existing file has the same name and size
+    Duplicate,
+    /// An existing file has the same name and creation date, and is not of lower quality
+    ///
+    /// See [Config::quality_heuristic].
+    LowerQuality,
+    /// An existing file has the same content
+    SameContent,
+    /// The same file already exists in the target directory
+    ExistsAtTarget,
+    /// The file was created before the configured minimum date
+    BeforeSince,
+    /// The file was created outside of the configured time of day or weekdays
+    OutsideTimeRange,
+    /// The file is smaller than the configured minimum size
+    TooSmall,
+    /// The file cannot be opened for reading
+    Unreadable,
+    /// An existing image looks the same by its perceptual hash
+    NearDuplicate,
+    /// The file is its own target, e.g. with a search path inside the output path
+    SourceIsTarget,
+    /// A different file appeared at the target after planning the copy, see [Config::force]
+    TargetExists,
+}
+
+/// Record of the action taken for a media file, written to the import report
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportRecord {
+    /// Path of the media file in the search paths
+    pub source: PathBuf,
+    /// Path the file was copied to
+    pub target: Option<PathBuf>,
+    /// Path of the existing file this file duplicates
+    pub existing: Option<PathBuf>,
+    /// Action taken
+    pub action: ImportAction,
+    /// File size in bytes
+    pub size: u64,
+    /// Resolved creation timestamp used for the date directory
+    pub created: Option<DateTime<FixedOffset>>,
+}
+
+/// Media file skipped as duplicate of an existing file
+#[derive(Debug, Clone, Serialize)]
+pub struct Duplicate {
+    /// Path of the media file in the search paths
+    pub source: PathBuf,
+    /// Path of the existing file
+    pub existing: PathBuf,
+    /// Reason the files are considered duplicates
+    pub reason: SkipReason,
+}
+
+/// Media file with the same name and day as an existing file but a different creation time
+#[derive(Debug, Clone, Serialize)]
+pub struct AmbiguousMatch {
+    /// Path of the media file in the search paths
+    pub source: PathBuf,
+    /// Creation timestamp of the media file
+    pub created: DateTime<FixedOffset>,
+    /// Path of the existing file
+    pub existing: PathBuf,
+    /// Creation timestamp of the existing file
+    pub existing_created: DateTime<FixedOffset>,
+}
+
+/// Action taken for a media file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ImportAction {
+    /// Copied (or moved) to the target
+    Copied,
+    /// Skipped, see [SkipReason::Duplicate]
+    SkippedDuplicate,
+    /// Skipped, see [SkipReason::LowerQuality]
+    SkippedLowerQuality,
+    /// Skipped, see [SkipReason::SameContent]
+    SkippedSameContent,
+    /// Skipped, see [SkipReason::ExistsAtTarget]
+    SkippedExistsAtTarget,
+    /// Skipped, see [SkipReason::BeforeSince]
+    SkippedBeforeSince,
+    /// Skipped, see [SkipReason::OutsideTimeRange]
+    SkippedOutsideTimeRange,
+    /// Skipped, see [SkipReason::TooSmall]
+    SkippedTooSmall,
+    /// Skipped, see [SkipReason::Unreadable]
+    SkippedUnreadable,
+    /// Skipped, see [SkipReason::NearDuplicate]
+    SkippedNearDuplicate,
+    /// Skipped, see [SkipReason::SourceIsTarget]
+    SkippedSourceIsTarget,
+    /// Skipped, see [SkipReason::TargetExists]
+    SkippedTargetExists,
+}
+
+impl From<SkipReason> for ImportAction {
+    fn from(reason: SkipReason) -> Self {
+        match reason {
+            SkipReason::Duplicate => Self::SkippedDuplicate,
+            SkipReason::LowerQuality => Self::SkippedLowerQuality,
+            SkipReason::SameContent => Self::SkippedSameContent,
+            SkipReason::ExistsAtTarget => Self::SkippedExistsAtTarget,
+            SkipReason::BeforeSince => Self::SkippedBeforeSince,
+            SkipReason::OutsideTimeRange => Self::SkippedOutsideTimeRange,
+            SkipReason::TooSmall => Self::SkippedTooSmall,
+            SkipReason::Unreadable => Self::SkippedUnreadable,
+            SkipReason::NearDuplicate => Self::SkippedNearDuplicate,
+            SkipReason::SourceIsTarget => Self::SkippedSourceIsTarget,
+            SkipReason::TargetExists => Self::SkippedTargetExists,
+        }
+    }
+}
+
+/// Statistics of an import run
+#[derive(Debug, Default, Clone)]
+pub struct Statistics {
+    /// Media files found in the search paths
+    pub found: usize,
+    /// Files skipped as duplicates or lower-quality versions
+    pub skipped: usize,
+    /// Files copied to the output path
+    pub copied: usize,
+    /// Files copied as higher-quality versions of existing files
+    pub copied_hq: usize,
+    /// Existing lower-quality files replaced by higher-quality copies
+    pub replaced: Vec<PathBuf>,
+    /// Files which failed to import
+    pub errors: usize,
+    /// Total size of copied files in bytes
+    pub bytes_copied: u64,
+    /// Total size of skipped files in bytes
+    pub bytes_skipped: u64,
+    /// Total size of files skipped as duplicates of existing files in bytes
+    pub bytes_duplicates: u64,
+    /// Total size of files linked instead of copied in bytes
+    pub bytes_linked: u64,
+    /// Files for which an existing file with the same name was found
+    pub name_existing: usize,
+    /// Existing files which failed to parse
+    ///
+    /// Files with unparseable exif data are still indexed by name and size.
+    pub parse_failed: usize,
+    /// Files imported although an existing file has the same name and day
+    pub ambiguous: Vec<AmbiguousMatch>,
+    /// Files skipped as duplicates of existing files
+    pub duplicates: Vec<Duplicate>,
+    /// Files skipped for being smaller than `min_file_size` or `min_size`
+    pub too_small: usize,
+    /// Files skipped for not being readable
+    pub unreadable: usize,
+    /// Number of files copied to each target directory, relative to its output path
+    pub copied_per_directory: HashMap<String, usize>,
+    /// The import was cancelled before all files were processed
+    pub cancelled: bool,
+    /// Media files left unprocessed after reaching the `limit` or cancelling
+    pub remaining: usize,
+    /// Number of copied files per device, see [exif_device]
+    ///
+    /// The devices are read along with the exif creation timestamps,
+    /// so files dated without their exif data, e.g. with `mtime-only`, are not counted.
+    pub copied_per_device: HashMap<String, usize>,
+    /// Existing files indexed before the import
+    pub indexed: usize,
+    /// Existing files parsed while indexing, the others were taken from the `index_cache`
+    pub index_parsed: usize,
+    /// Durations of the import phases
+    pub timings: Timings,
+}
+
+/// Durations of the phases of an import run
+///
+/// Logged in the summary with [Config::profile].
+#[derive(Debug, Default, Clone)]
+pub struct Timings {
+    /// Walking the existing paths and indexing their files
+    pub index: Duration,
+    /// Walking the search paths to find media files
+    pub walk: Duration,
+    /// Parsing exif data and track info, summed over all threads
+    ///
+    /// Part of the indexing and the import of files, and not exclusive to this run
+    /// if other imports run in the same process at the same time.
+    pub exif: Duration,
+    /// Copying files, including the time of parallel copies waiting for each other
+    pub copy: Duration,
+}
+
+impl Timings {
+    /// Log the durations and throughputs of the import with `stats`
+    fn log(&self, stats: &Statistics) {
+        fn per_sec(count: f64, duration: Duration) -> f64 {
+            count / duration.as_secs_f64().max(f64::EPSILON)
+        }
+
+        info!(
+            "Indexed {} existing files in {:.2?} ({:.0} files/s)",
+            stats.indexed,
+            self.index,
+            per_sec(stats.indexed as f64, self.index),
+        );
+        info!(
+            "Found {} files in the search paths in {:.2?} ({:.0} files/s)",
+            stats.found,
+            self.walk,
+            per_sec(stats.found as f64, self.walk),
+        );
+        info!("Parsed exif data in {:.2?} in total", self.exif);
+        info!(
+            "Copied {} files in {:.2?} ({}/s)",
+            stats.copied,
+            self.copy,
+            human_bytes(per_sec(stats.bytes_copied as f64, self.copy) as u64),
+        );
+    }
+}
+
+/// Total time spent parsing exif data in nanoseconds, see [Timings::exif]
+static EXIF_NANOS: AtomicU64 = AtomicU64::new(0);
+
+impl Statistics {
+    /// Log a summary of the import run
+    ///
+    /// With `verbose_summary`, the number of files copied to each target directory is included,
+    /// with `profile` the durations of the import phases.
+    fn log_summary(&self, config: &Config) {
+        if config.dry_run {
+            info!(
+                "Dry run: would copy {} files ({}), skipped {} files ({})",
+                self.copied,
+                human_bytes(self.bytes_copied),
+                self.skipped,
+                human_bytes(self.bytes_skipped),
+            );
+        } else {
+            info!(
+                "Copied {} files ({}), skipped {} files ({})",
+                self.copied,
+                human_bytes(self.bytes_copied),
+                self.skipped,
+                human_bytes(self.bytes_skipped),
+            );
+        }
+        if self.bytes_duplicates > 0 {
+            info!(
+                "Avoided copying {} of duplicates",
+                human_bytes(self.bytes_duplicates)
+            );
+        }
+        if self.bytes_linked > 0 {
+            info!(
+                "Saved {} by linking instead of copying",
+                human_bytes(self.bytes_linked)
+            );
+        }
+        if self.remaining > 0 {
+            info!("{} files remain unprocessed", self.remaining);
+        }
+        if self.cancelled {
+            warn!("Import was cancelled before all files were processed");
+        }
+        if self.errors > 0 {
+            warn!("{} files failed to import", self.errors);
+        }
+        if self.parse_failed > 0 {
+            info!("{} files failed to parse", self.parse_failed);
+        }
+        if self.too_small > 0 {
+            info!("{} files were too small to import", self.too_small);
+        }
+        if self.unreadable > 0 {
+            warn!("{} files were not readable", self.unreadable);
+        }
+        if !self.ambiguous.is_empty() {
+            warn!(
+                "{} files share name and day with an existing file",
+                self.ambiguous.len()
+            );
+        }
+        if !self.copied_per_device.is_empty() {
+            let mut devices: Vec<_> = self.copied_per_device.iter().collect();
+            devices.sort_unstable_by(|(d1, c1), (d2, c2)| c2.cmp(c1).then(d1.cmp(d2)));
+            let devices: Vec<_> = devices
+                .into_iter()
+                .map(|(device, count)| format!("{device}: {count}"))
+                .collect();
+            info!("Devices: {}", devices.join(", "));
+        }
+        if config.verbose_summary {
+            let mut directories: Vec<_> = self.copied_per_directory.iter().collect();
+            directories.sort_unstable();
+            let verb = if config.dry_run {
+                "Would copy"
+            } else {
+                "Copied"
+            };
+            for (directory, count) in directories {
+                info!("{verb} {count} files to {directory}");
+            }
+        }
+        if config.profile {
+            self.timings.log(self);
+        }
+        info!("{self:#?}");
+    }
+}
+
+/// Index the existing media files, including the output paths with `index_output` or `auto_since`
+///
+/// With `auto_since`, the returned configuration continues after the latest file
+/// of previous imports. Indexing stops early once the `cancel_flag` is set.
+fn index_existing<'a>(
+    config: &'a Config,
+    walk: &MediaWalk,
+    cancel_flag: Option<&AtomicBool>,
+) -> Result<(MediaFiles, Cow<'a, Config>)> {
+    let outputs: Vec<&String> = std::iter::once(&config.output_path)
+        .chain(config.output_routes.iter().map(|r| &r.path))
+        .collect();
+    let mut existing_paths = config.existing_paths.clone();
+    if config.index_output || config.auto_since {
+        for &output in &outputs {
+            if !existing_paths.contains(output) {
+                existing_paths.push(output.clone());
+            }
+        }
+    }
+    let existing = MediaFiles::from_paths(&existing_paths, walk, config, cancel_flag)?;
+
+    // Continue after the latest file of previous imports
+    let config = match config
+        .auto_since
+        .then(|| existing.latest_below(&outputs, config))
+        .flatten()
+    {
+        Some(latest) if config.since.is_none_or(|since| since < latest) => {
+            info!("Importing files created at or after {latest}, the latest in the output");
+            Cow::Owned(Config {
+                since: Some(latest),
+                ..config.clone()
+            })
+        }
+        _ => Cow::Borrowed(config),
+    };
+    Ok((existing, config))
+}
+
+/// Synchronize files to the output path which are not found in `existing`
+///
+/// With `dry_run`, the duplicate detection runs as usual
+/// but copies and directory creations are only logged.
+/// Errors importing a file are logged and counted unless `strict` is set.
+///
+/// With `copy_threads`, all decisions are made first and the copies run in parallel afterwards.
+/// With a `limit`, the planned copies run whenever they reach the limit,
+/// and the decisions continue if some of them were skipped or failed.
+/// Each copy is reported to the `observer` and followed by the `post_copy_command`
+/// as soon as it finished. A `strict` import then fails after the running copies have finished.
+///
+/// Files are decided on one after another in the order of the search paths,
+/// and sorted by name within each directory, followed by the entries of zip archives.
+/// Collision suffixes and the order of logs and reports are therefore reproducible,
+/// only the order of parallel copies varies.
+fn sync_media_files(
+    existing: &MediaFiles,
+    walk: &MediaWalk,
+    raw_extensions: &ExtensionSet,
+    config: &Config,
+    observer: &mut impl ImportObserver,
+) -> Result<Statistics> {
+    let _span = info_span!("sync").entered();
+    let pool = config
+        .copy_threads
+        .map(|threads| ThreadPoolBuilder::new().num_threads(threads).build())
+        .transpose()?;
+    let throttle = config.max_read_bytes_per_sec.map(Throttle::new);
+    let cancel_flag = observer.cancel_flag();
+    let cancelled = || {
+        cancel_flag
+            .as_deref()
+            .is_some_and(|c| c.load(Ordering::Relaxed))
+    };
+    // Number of copies and planned copies, to stop at the `limit`
+    let copies = Cell::new(0);
+    let limit_reached = || config.limit.is_some_and(|limit| copies.get() >= limit);
+
+    // Crawl through search paths
+    // Skip the output paths, so that previous imports into a search path are not imported again
+    let outputs = config.canonical_outputs();
+    let start = Instant::now();
+    let files: Vec<PathBuf> = walk
+        .find_search_files(&config.search_paths, &outputs)
+        .collect();
+    let walk_time = start.elapsed();
+    let date_siblings = date_siblings(&files, raw_extensions, config);
+
+    #[cfg(feature = "zip")]
+    let mut archives = config
+        .search_paths
+        .iter()
+        .map(Path::new)
+        .filter(|path| is_zip_archive(path))
+        .map(|path| ZipMedia::open(path, |e| !walk.is_excluded(e) && walk.is_search_file(e)))
+        .collect::<Result<Vec<_>>>()?;
+    #[cfg(feature = "zip")]
+    let total = files.len() + archives.iter().map(ZipMedia::len).sum::<usize>();
+    #[cfg(not(feature = "zip"))]
+    let total = files.len();
+    observer.on_search_complete(total);
+
+    let mut outcomes = Outcomes::default();
+    outcomes.stats.timings.walk = walk_time;
+    let mut plan = TargetPlan {
+        sidecars: Sidecars::new(&files, config)?,
+        ..Default::default()
+    };
+    if let Some(window) = config.burst_window_secs {
+        plan.metadata = creation_metadata_of(&files, &date_siblings, config);
+        plan.bursts = burst_directories(&plan.metadata, window, config);
+    }
+    let mut pending = Vec::new();
+
+    // Import a media file on disk or, if given, read into memory
+    let mut import = |path: PathBuf, memory: Option<Result<MemoryFile>>| -> Result<()> {
+        outcomes.stats.found += 1;
+        observer.on_file_found(&path);
+
+        let memory = match memory {
+            Some(Ok(file)) => Some(file),
+            Some(Err(e)) => return outcomes.failed(&path, e, config.strict, observer),
+            None => None,
+        };
+        let (source, date_source) = match &memory {
+            Some(file) => (Source::Memory(file), Source::Memory(file)),
+            // Date files by their raw or live photo sibling if there is one
+            None => (
+                Source::File(&path),
+                Source::File(date_siblings.get(&path).unwrap_or(&path)),
+            ),
+        };
+        // Files in memory are transferred right away instead of keeping them around
+        let deferred = pool.is_some() && memory.is_none();
+
+        let result = source.size().and_then(|file_size| {
+            let decision = decide(
+                source,
+                file_size,
+                date_source,
+                existing,
+                &plan,
+                config,
+                &mut outcomes.stats,
+            )?;
+            let (decision, transferred) = match decision {
+                Decision::Copy(copy) if !deferred => {
+                    match check_target(source, &copy.target, config)? {
+                        Some((reason, existing)) => {
+                            (Decision::Skip(reason, existing), Transferred::default())
+                        }
+                        None => {
+                            let start = Instant::now();
+                            let transferred = transfer(source, &copy, config, throttle.as_ref())?;
+                            outcomes.stats.timings.copy += start.elapsed();
+                            (Decision::Copy(copy), transferred)
+                        }
+                    }
+                }
+                decision => (decision, Transferred::default()),
+            };
+            Ok((file_size, decision, transferred))
+        });
+
+        match result {
+            Ok((file_size, Decision::Skip(reason, existing), _)) => {
+                outcomes.skipped(path, file_size, reason, existing, observer);
+            }
+            Ok((file_size, Decision::Copy(copy), transferred)) => {
+                copies.set(copies.get() + 1);
+                plan.reserved.insert(copy.target.clone());
+                if config.dry_run {
+                    report_new_directory(&copy.target, &mut plan.new_directories);
+                }
+                if deferred {
+                    pending.push((path, file_size, copy));
+                    // Run the planned copies, so that only the successful ones count towards the limit
+                    if let Some(pool) = &pool
+                        && limit_reached()
+                    {
+                        let pending = std::mem::take(&mut pending);
+                        copy_pending(
+                            pool,
+                            pending,
+                            &mut outcomes,
+                            observer,
+                            throttle.as_ref(),
+                            config,
+                        )?;
+                        copies.set(outcomes.stats.copied);
+                    }
+                } else {
+                    run_post_copy_command(&copy.target, config);
+                    outcomes.copied(path, file_size, copy, transferred, observer);
+                }
+            }
+            Err(e) => outcomes.failed(&path, e, config.strict, observer)?,
+        }
+        Ok(())
+    };
+
+    for path in files {
+        if cancelled() || limit_reached() {
+            break;
+        }
+        import(path, None)?;
+    }
+
+    #[cfg(feature = "zip")]
+    'archives: for archive in &mut archives {
+        for (path, file) in archive.read_entries() {
+            if cancelled() || limit_reached() {
+                break 'archives;
+            }
+            import(path, Some(file))?;
+        }
+    }
+
+    if let Some(pool) = &pool {
+        copy_pending(
+            pool,
+            pending,
+            &mut outcomes,
+            observer,
+            throttle.as_ref(),
+            config,
+        )?;
+    }
+
+    outcomes.stats.cancelled = cancelled();
+    outcomes.stats.remaining += total.saturating_sub(outcomes.stats.found);
+    let Outcomes {
+        stats,
+        records,
+        manifest,
+    } = outcomes;
+
+    if let Some(report_path) = &config.report_path {
+        fs::write(report_path, serde_json::to_vec_pretty(&records)?)
+            .with_context(|| format!("failed to write report {report_path}"))?;
+        debug!("Wrote report of {} files to {report_path}", records.len());
+    }
+
+    if let Some(report_path) = &config.ambiguous_report_path {
+        fs::write(report_path, serde_json::to_vec_pretty(&stats.ambiguous)?)
+            .with_context(|| format!("failed to write report {report_path}"))?;
+        debug!(
+            "Wrote report of {} ambiguous files to {report_path}",
+            stats.ambiguous.len()
+        );
+    }
+
+    if !manifest.is_empty() {
+        append_manifest(
+            &manifest,
+            config.content_hash_algo,
+            Path::new(&config.output_path),
+        )?;
+    }
+
+    Ok(stats)
+}
+
+/// Planned state of the target directories
+#[derive(Default)]
+struct TargetPlan {
+    /// Targets of planned copies, which may not exist yet
+    reserved: HashSet<PathBuf>,
+    /// Map from media files shot in bursts to the subdirectory of their burst
+    bursts: HashMap<PathBuf, String>,
+    /// Creation timestamps and devices of media files resolved while planning,
+    /// to be reused by [decide]
+    metadata: HashMap<PathBuf, Metadata>,
+    /// Sidecars in the directories of the media files
+    sidecars: Sidecars,
+    /// Date directories reported as to be created in a dry run
+    new_directories: HashSet<PathBuf>,
+}
+
+/// Map media files to the raw or, with `pair_live_photos`, Live Photo sibling to date them by
+fn date_siblings(
+    files: &[PathBuf],
+    raw_extensions: &ExtensionSet,
+    config: &Config,
+) -> HashMap<PathBuf, PathBuf> {
+    let mut date_siblings = raw_siblings(files, raw_extensions);
+    if config.pair_live_photos {
+        for (video, image) in live_photo_siblings(files) {
+            date_siblings.entry(video).or_insert(image);
+        }
+    }
+    date_siblings
+}
+
+/// Map media `files` to their creation metadata, dated by their sibling if they have one
+fn creation_metadata_of(
+    files: &[PathBuf],
+    date_siblings: &HashMap<PathBuf, PathBuf>,
+    config: &Config,
+) -> HashMap<PathBuf, Metadata> {
+    files
+        .iter()
+        .map(|path| {
+            let date_source = date_siblings.get(path).unwrap_or(path);
+            (path.clone(), creation_metadata(date_source, config))
+        })
+        .collect()
+}
+
+/// Map media files shot in bursts to the subdirectory of their burst, e.g. `burst_143022`
+///
+/// Files with the same name prefix form a burst
+/// if each was created within `window_secs` of the previous one.
+/// Files without a creation timestamp are never part of a burst.
+/// The subdirectory is named after the creation time of the first shot.
+fn burst_directories(
+    metadata: &HashMap<PathBuf, Metadata>,
+    window_secs: u64,
+    config: &Config,
+) -> HashMap<PathBuf, String> {
+    let window = TimeDelta::seconds(i64::try_from(window_secs).unwrap_or(i64::MAX));
+
+    let mut shots: Vec<_> = metadata
+        .iter()
+        .filter_map(|(path, metadata)| {
+            let prefix = path
+                .file_stem()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .trim_end_matches(|c: char| c.is_ascii_digit())
+                .to_owned();
+            Some((prefix, metadata.created?, path))
+        })
+        .collect();
+    shots.sort();
+
+    let mut bursts = HashMap::new();
+    for group in shots.chunk_by(|(p1, t1, _), (p2, t2, _)| p1 == p2 && *t2 - *t1 <= window) {
+        if let [(_, first, _), _, ..] = group {
+            let first = match config.bucket_timezone {
+                Some(timezone) => first.with_timezone(&timezone).fixed_offset(),
+                None => *first,
+            };
+            let name = first.format("burst_%H%M%S").to_string();
+            debug!("Grouping {} files into {name}", group.len());
+            for (_, _, path) in group {
+                bursts.insert((*path).clone(), name.clone());
+            }
+        }
+    }
+
+    bursts
+}
+
+/// Outcomes of importing the media files of the search paths
+#[derive(Default)]
+struct Outcomes {
+    /// Statistics of the import
+    stats: Statistics,
+    /// Records for the import report
+    records: Vec<ImportRecord>,
+    /// Hashes and paths of copied files for the manifest
+    manifest: Vec<(Vec<u8>, PathBuf)>,
+}
+
+impl Outcomes {
+    /// Record the media file at `path` as skipped, duplicating the `existing` file if any
+    fn skipped(
+        &mut self,
+        path: PathBuf,
+        file_size: u64,
+        reason: SkipReason,
+        existing: Option<PathBuf>,
+        observer: &mut impl ImportObserver,
+    ) {
+        debug!(
+            path = %path.display(),
+            size = file_size,
+            action = ?ImportAction::from(reason),
+            existing = existing.as_ref().map(|e| e.display().to_string()),
+            "Skipped file",
+        );
+        self.stats.skipped += 1;
+        self.stats.bytes_skipped += file_size;
+        match reason {
+            SkipReason::TooSmall => self.stats.too_small += 1,
+            SkipReason::Unreadable => self.stats.unreadable += 1,
+            _ => {}
+        }
+        observer.on_file_skipped(&path, reason);
+        if let Some(existing) = &existing {
+            self.stats.bytes_duplicates += file_size;
+            self.stats.duplicates.push(Duplicate {
+                source: path.clone(),
+                existing: existing.clone(),
+                reason,
+            });
+        }
+        self.records.push(ImportRecord {
+            source: path,
+            target: None,
+            existing,
+            action: reason.into(),
+            size: file_size,
+            created: None,
+        });
+    }
+
+    /// Record the media file at `path` as copied, with the hash of the copy if computed
+    fn copied(
+        &mut self,
+        path: PathBuf,
+        file_size: u64,
+        copy: PlannedCopy,
+        transferred: Transferred,
+        observer: &mut impl ImportObserver,
+    ) {
+        debug!(
+            path = %path.display(),
+            size = file_size,
+            action = ?ImportAction::Copied,
+            target = %copy.target.display(),
+            "Copied file",
+        );
+        observer.on_file_copied(&path, &copy.target);
+        self.stats.copied += 1;
+        self.stats.bytes_copied += file_size;
+        *self
+            .stats
+            .copied_per_directory
+            .entry(copy.directory)
+            .or_default() += 1;
+        if let Some(device) = copy.device {
+            *self.stats.copied_per_device.entry(device).or_default() += 1;
+        }
+        if let Some(replaces) = copy.replaces {
+            self.stats.copied_hq += 1;
+            self.stats.replaced.push(replaces);
+        }
+        if transferred.linked {
+            self.stats.bytes_linked += file_size;
+        }
+        if let Some(hash) = transferred.hash {
+            self.manifest.push((hash, copy.target.clone()));
+        }
+        self.records.push(ImportRecord {
+            source: path,
+            target: Some(copy.target),
+            existing: None,
+            action: ImportAction::Copied,
+            size: file_size,
+            created: copy.created,
+        });
+    }
+
+    /// Record the failed import of the media file at `path`, or return the error with `strict`
+    fn failed(
+        &mut self,
+        path: &Path,
+        e: anyhow::Error,
+        strict: bool,
+        observer: &mut impl ImportObserver,
+    ) -> Result<()> {
+        if strict {
+            return Err(e);
+        }
+        warn!(path = %path.display(), error = %format!("{e:#}"), "Failed to import file");
+        observer.on_file_failed(path, &e);
+        self.stats.errors += 1;
+        Ok(())
+    }
+}
+
+/// Append `entries` of hashes and file paths to the manifest of `algorithm` in `output_path`
+///
+/// Paths below `output_path` are written relative to it, so that the manifest can be
+/// checked from within `output_path`.
+fn append_manifest(
+    entries: &[(Vec<u8>, PathBuf)],
+    algorithm: HashAlgorithm,
+    output_path: &Path,
+) -> Result<()> {
+    let mut lines = String::new();
+    for (hash, path) in entries {
+        for byte in hash {
+            write!(lines, "{byte:02x}")?;
+        }
+        let path = path.strip_prefix(output_path).unwrap_or(path);
+        writeln!(lines, "  {}", path.display())?;
+    }
+
+    fs::create_dir_all(output_path)
+        .with_context(|| format!("failed to create {}", output_path.display()))?;
+    let manifest_path = output_path.join(format!("{MANIFEST_FILE_STEM}.{algorithm}"));
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&manifest_path)
+        .with_context(|| format!("failed to open {}", manifest_path.display()))?;
+    io::Write::write_all(&mut file, lines.as_bytes())
+        .with_context(|| format!("failed to write {}", manifest_path.display()))?;
+    debug!(
+        "Appended {} entries to {}",
+        entries.len(),
+        manifest_path.display()
+    );
+    Ok(())
+}
+
+/// File stem of the checksum manifest in the output path, extended by the hash algorithm
+const MANIFEST_FILE_STEM: &str = "manifest";
+
+/// Decision on importing a media file
+enum Decision {
+    /// Skip the file, duplicating the existing file if any
+    Skip(SkipReason, Option<PathBuf>),
+    /// Copy the file
+    Copy(PlannedCopy),
+}
+
+/// Planned copy of a media file
+struct PlannedCopy {
+    /// Target path
+    target: PathBuf,
+    /// Resolved creation timestamp, `None` for undated files
+    created: Option<DateTime<FixedOffset>>,
+    /// Existing file of which this file is a higher-quality version
+    replaces: Option<PathBuf>,
+    /// Target directory relative to the output path, `.` for the output path itself
+    directory: String,
+    /// Sidecars of the media file to transfer along with it
+    sidecars: Vec<PathBuf>,
+    /// Device read from the exif data while dating the file, see [exif_device]
+    device: Option<String>,
+}
+
+/// Decide whether to import the media file `source` of size `file_size` and where to
+///
+/// The file is dated by the file `date_source`.
+/// Paths reserved in `plan` are treated as taken by planned copies.
+fn decide(
+    source: Source,
+    file_size: u64,
+    date_source: Source,
+    existing: &MediaFiles,
+    plan: &TargetPlan,
+    config: &Config,
+    stats: &mut Statistics,
+) -> Result<Decision> {
+    let path = source.path();
+    if file_size < config.min_size(path) {
+        info!("Skipping {} of only {file_size} bytes", path.display());
+        return Ok(Decision::Skip(SkipReason::TooSmall, None));
+    }
+    // Unreadable files would only be dated by their modification time otherwise
+    if let Source::File(path) = source
+        && let Err(e) = File::open(path)
+    {
+        warn!("Skipping unreadable {}: {e}", path.display());
+        return Ok(Decision::Skip(SkipReason::Unreadable, None));
+    }
+
+    // Resolved at most once, as reading the exif data via a slow connection is expensive
+    let metadata = OnceCell::new();
+    let created = || {
+        metadata
+            .get_or_init(|| match plan.metadata.get(path) {
+                Some(metadata) => metadata.clone(),
+                None => date_source.creation_metadata(config),
+            })
+            .created
+    };
+
+    // Check the minimum date, time of day and weekday before the more expensive duplicate checks
+    if config.since.is_some() || config.time_range.is_some() || !config.weekdays.is_empty() {
+        let timestamp = created();
+        if let Some(since) = config.since
+            && timestamp.unwrap_or_default() < since
+        {
+            debug!("Skipping {} created before {since}", path.display());
+            return Ok(Decision::Skip(SkipReason::BeforeSince, None));
+        }
+        if let Some(range @ (start, end)) = config.time_range
+            && !timestamp.is_some_and(|t| in_time_range(&t, range, config.bucket_timezone))
+        {
+            debug!(
+                "Skipping {} created outside of {start}-{end}",
+                path.display()
+            );
+            return Ok(Decision::Skip(SkipReason::OutsideTimeRange, None));
+        }
+        if !config.weekdays.is_empty()
+            && !timestamp.is_some_and(|t| on_weekdays(&t, &config.weekdays, config.bucket_timezone))
+        {
+            debug!("Skipping {} created on another weekday", path.display());
+            return Ok(Decision::Skip(SkipReason::OutsideTimeRange, None));
+        }
+    }
+
+    let replaces = match check_existing(source, file_size, created, existing, config, stats)? {
+        Check::Skip(reason, existing) => return Ok(Decision::Skip(reason, Some(existing))),
+        Check::Import { replaces } => replaces,
+    };
+
+    // Find a free target location
+    let created = created();
+    let mut date_path = target_directory(path, created.as_ref(), config)?;
+    if let Some(burst) = plan.bursts.get(path) {
+        date_path.push(burst);
+    }
+    let file_name = target_file_name(
+        path,
+        &created.unwrap_or_default(),
+        config.filename_template.as_deref(),
+        config.bucket_timezone,
+    )?;
+    let target = date_path.join(file_name);
+    if let Source::File(path) = source
+        && is_same_file(path, &target)
+    {
+        info!("Skipping {} which is already at its target", path.display());
+        return Ok(Decision::Skip(SkipReason::SourceIsTarget, None));
+    }
+    match unique_target_path(source, target, &plan.reserved, config)? {
+        TargetPath::Free(target) => {
+            let directory = match date_path.strip_prefix(config.output_root(path)) {
+                Ok(relative) if relative.as_os_str().is_empty() => ".".to_owned(),
+                Ok(relative) => relative.display().to_string(),
+                Err(_) => date_path.display().to_string(),
+            };
+            let sidecars = match source {
+                Source::File(path) => plan.sidecars.of(path),
+                Source::Memory(_) => Vec::new(),
+            };
+            Ok(Decision::Copy(PlannedCopy {
+                target,
+                created,
+                replaces,
+                directory,
+                sidecars,
+                device: metadata.get().and_then(|m| m.device.clone()),
+            }))
+        }
+        TargetPath::SameContent(existing) => {
+            debug!(
+                "Skipping {} which already exists at {}",
+                path.display(),
+                existing.display()
+            );
+            Ok(Decision::Skip(SkipReason::ExistsAtTarget, Some(existing)))
+        }
+    }
+}
+
+/// Run the `pending` copies in parallel in the `pool`, reporting each to the `observer`
+///
+/// Copies not started yet when the import is cancelled are counted as remaining.
+/// A `strict` import fails after the running copies have finished.
+fn copy_pending(
+    pool: &ThreadPool,
+    pending: Vec<(PathBuf, u64, PlannedCopy)>,
+    outcomes: &mut Outcomes,
+    observer: &mut impl ImportObserver,
+    throttle: Option<&Throttle>,
+    config: &Config,
+) -> Result<()> {
+    let cancel_flag = observer.cancel_flag();
+    let cancelled = || {
+        cancel_flag
+            .as_deref()
+            .is_some_and(|c| c.load(Ordering::Relaxed))
+    };
+    let start = Instant::now();
+    let (sender, results) = mpsc::channel();
+    // The outcomes are reported while the other copies are still running
+    let first_error = thread::scope(|scope| -> Result<_> {
+        scope.spawn(move || {
+            pool.install(|| {
+                pending
+                    .into_par_iter()
+                    .for_each_with(sender, |sender, (path, file_size, copy)| {
+                        // Copies not started yet are left unprocessed
+                        if cancelled() {
+                            let _ = sender.send((path, file_size, copy, None));
+                            return;
+                        }
+                        let source = Source::File(&path);
+                        let result =
+                            check_target(source, &copy.target, config).and_then(
+                                |skip| match skip {
+                                    Some(reason) => Ok(Err(reason)),
+                                    None => transfer(source, &copy, config, throttle).map(Ok),
+                                },
+                            );
+                        let _ = sender.send((path, file_size, copy, Some(result)));
+                    })
+            })
+        });
+
+        let mut first_error = None;
+        for (path, file_size, copy, result) in results {
+            match result {
+                Some(Ok(Ok(transferred))) => {
+                    run_post_copy_command(&copy.target, config);
+                    outcomes.copied(path, file_size, copy, transferred, observer);
+                }
+                Some(Ok(Err((reason, existing)))) => {
+                    outcomes.skipped(path, file_size, reason, existing, observer);
+                }
+                Some(Err(e)) if config.strict => {
+                    first_error.get_or_insert(e);
+                }
+                Some(Err(e)) => outcomes.failed(&path, e, false, observer)?,
+                None => outcomes.stats.remaining += 1,
+            }
+        }
+        Ok(first_error)
+    })?;
+    outcomes.stats.timings.copy += start.elapsed();
+    if let Some(e) = first_error {
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Check if a file appeared at the planned `target` of `source` since planning the copy
+///
+/// Returns the reason to skip `source` and the duplicated file, if the file should be kept.
+/// This is the case for a file of the same content and, without `force`, any other file.
+fn check_target(
+    source: Source,
+    target: &Path,
+    config: &Config,
+) -> Result<Option<(SkipReason, Option<PathBuf>)>> {
+    if !target.exists() {
+        return Ok(None);
+    }
+
+    let algorithm = config.content_hash_algo;
+    if source.size()? == file_size(target)?
+        && source.content_hash(algorithm)? == content_hash(target, algorithm)?
+    {
+        debug!(
+            "Skipping {} which appeared at {}",
+            source.path().display(),
+            target.display()
+        );
+        return Ok(Some((SkipReason::ExistsAtTarget, Some(target.to_owned()))));
+    }
+
+    if config.force {
+        warn!(
+            "Overwriting {} which appeared after planning the copy of {}",
+            target.display(),
+            source.path().display()
+        );
+        return Ok(None);
+    }
+    warn!(
+        "Not overwriting {} which appeared after planning the copy of {}, use --force to overwrite",
+        target.display(),
+        source.path().display()
+    );
+    Ok(Some((SkipReason::TargetExists, None)))
+}
+
+/// Log that the date directory of `target` would be created in a dry run
+///
+/// Each directory is only reported once, as tracked in `reported`.
+fn report_new_directory(target: &Path, reported: &mut HashSet<PathBuf>) {
+    if let Some(date_path) = target.parent()
+        && !date_path.is_dir()
+        && reported.insert(date_path.to_owned())
+    {
+        info!("Would create date directory {}", date_path.display());
+    }
+}
+
+/// Copy or move the media file `source` to the target of `copy`, creating its directory if needed
+///
+/// Files in memory are written to the target, moving them only copies them.
+/// With `dry_run`, the actions are only logged.
+/// Copies are limited to the rate of `throttle`, if given.
+/// With `link_mode`, files on the device of the target are linked instead of copied.
+/// Returns the content hash of the target with `write_manifest`.
+fn transfer(
+    source: Source,
+    copy: &PlannedCopy,
+    config: &Config,
+    throttle: Option<&Throttle>,
+) -> Result<Transferred> {
+    let target = &copy.target;
+    let date_path = target.parent().unwrap();
+    let sidecars = sidecar_targets(source.path(), &copy.sidecars, target);
+
+    if config.dry_run {
+        let action = match source {
+            Source::File(_) if config.move_files => "move",
+            Source::File(path) if link_mode(path, target, config).is_some() => "link",
+            _ => "copy",
+        };
+        info!(
+            "Would {action} {} to {}",
+            source.path().display(),
+            target.display()
+        );
+        for (sidecar, sidecar_target) in sidecars {
+            info!(
+                "Would {action} sidecar {} to {}",
+                sidecar.display(),
+                sidecar_target.display()
+            );
+        }
+        return Ok(Transferred::default());
+    }
+
+    if !date_path.is_dir() {
+        debug!("Creating date directory {}", date_path.display());
+        fs::create_dir_all(date_path)?;
+    }
+
+    // Read before the transfer, as moving removes the source
+    let times = if config.preserve_timestamps {
+        Some(source_times(source)?)
+    } else {
+        None
+    };
+
+    // Rotated files are written like files in memory, so that the target is never rewritten
+    #[cfg(feature = "auto-rotate")]
+    let rotated = if config.auto_rotate
+        && ExtensionSet(HashSet::from(["jpg".into(), "jpeg".into()])).matches(target)
+    {
+        source.rotated().unwrap_or_else(|e| {
+            warn!(
+                "Keeping the orientation of {}, which failed to rotate: {e:#}",
+                source.path().display()
+            );
+            None
+        })
+    } else {
+        None
+    };
+    #[cfg(not(feature = "auto-rotate"))]
+    let rotated: Option<Vec<u8>> = None;
+
+    let mut linked = false;
+    match (source, rotated) {
+        (source, Some(data)) => {
+            let file = MemoryFile {
+                path: source.path().to_owned(),
+                data,
+                modified: None,
+            };
+            write_file(&file, target, config)?;
+            if let Source::File(path) = source
+                && config.move_files
+            {
+                remove_moved(path, target, config);
+            }
+            debug!(
+                "Wrote {} rotated according to its orientation to {}",
+                file.path.display(),
+                target.display()
+            );
+        }
+        (Source::File(path), None) if config.move_files => {
+            move_file(path, target, config, throttle)?;
+            debug!("Moved {} to {}", path.display(), target.display());
+        }
+        (Source::File(path), None) => {
+            linked = copy_or_link_file(path, target, config, throttle)?;
+        }
+        (Source::Memory(file), None) => {
+            write_file(file, target, config)?;
+            debug!("Wrote {} to {}", file.path.display(), target.display());
+        }
+    }
+
+    #[cfg(feature = "backfill-exif")]
+    if config.backfill_exif
+        && let Some(created) = &copy.created
+        && ExtensionSet(HashSet::from(["jpg".into(), "jpeg".into()])).matches(target)
+        && crate::backfill::backfill_date(target, created)?
+    {
+        debug!("Wrote creation date {created} into {}", target.display());
+    }
+
+    // Linked files already share their times with the source
+    if let Some(times) = times
+        && !linked
+    {
+        set_times(target, times)?;
+    }
+
+    for (sidecar, sidecar_target) in sidecars {
+        let result = if config.move_files {
+            move_file(&sidecar, &sidecar_target, config, throttle)
+        } else {
+            copy_or_link_file(&sidecar, &sidecar_target, config, throttle).map(|_| ())
+        };
+        match result {
+            Ok(()) => debug!(
+                "Transferred sidecar {} to {}",
+                sidecar.display(),
+                sidecar_target.display()
+            ),
+            Err(e) => warn!("Failed to transfer sidecar {}: {e:#}", sidecar.display()),
+        }
+    }
+
+    let hash = if config.write_manifest {
+        Some(content_hash(target, config.content_hash_algo)?)
+    } else {
+        None
+    };
+    Ok(Transferred { hash, linked })
+}
+
+/// Outcome of transferring a media file, see [transfer]
+#[derive(Default)]
+struct Transferred {
+    /// Content hash of the target with `write_manifest`
+    hash: Option<Vec<u8>>,
+    /// The target was linked to the source instead of copying it
+    linked: bool,
+}
+
+/// Kind of link to create from `target` to `source` with `link_mode`, if both are on one device
+///
+/// The device of `target` is the one of its closest existing ancestor.
+fn link_mode(source: &Path, target: &Path, config: &Config) -> Option<LinkMode> {
+    let mode = config.link_mode?;
+    let target_dir = target.ancestors().skip(1).find(|dir| dir.exists())?;
+    same_device(source, target_dir).then_some(mode)
+}
+
+/// Check if the files at `a` and `b` are on the same device
+///
+/// Devices are only compared on Unix, elsewhere failures to link fall back to copying.
+fn same_device(a: &Path, b: &Path) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        match (fs::metadata(a), fs::metadata(b)) {
+            (Ok(a), Ok(b)) => a.dev() == b.dev(),
+            _ => false,
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (a, b);
+        true
+    }
+}
+
+/// Link `target` to `source` with `link_mode` if both are on the same device, or copy it
+///
+/// Returns `true` if the file was linked.
+/// Failures to link are logged and the file is copied instead.
+fn copy_or_link_file(
+    source: &Path,
+    target: &Path,
+    config: &Config,
+    throttle: Option<&Throttle>,
+) -> Result<bool> {
+    if let Some(mode) = link_mode(source, target, config) {
+        match link_file(source, target, mode) {
+            Ok(()) => {
+                debug!("Linked {} to {}", target.display(), source.display());
+                return Ok(true);
+            }
+            Err(e) => warn!(
+                "Failed to link {} to {}, copying it instead: {e:#}",
+                target.display(),
+                source.display()
+            ),
+        }
+    }
+    copy_file(source, target, config, throttle)?;
+    debug!("Copied {} to {}", source.display(), target.display());
+    Ok(false)
+}
+
+/// Create `target` as a link of the kind `mode` to `source`
+///
+/// The link is created atomically, see [write_atomically].
+/// Symbolic links point to the canonical path of `source`.
+fn link_file(source: &Path, target: &Path, mode: LinkMode) -> Result<()> {
+    write_atomically(target, |temp| {
+        match mode {
+            LinkMode::Hard => fs::hard_link(source, temp),
+            LinkMode::Sym => {
+                let source = fs::canonicalize(source)?;
+                #[cfg(unix)]
+                let result = std::os::unix::fs::symlink(source, temp);
+                #[cfg(windows)]
+                let result = std::os::windows::fs::symlink_file(source, temp);
+                result
+            }
+        }
+        .with_context(|| format!("failed to link {}", source.display()))
+    })
+}
+
+/// Modification and access time of `source` to preserve on its target
+fn source_times(source: Source) -> Result<FileTimes> {
+    match source {
+        Source::File(path) => {
+            let metadata = fs::metadata(path)
+                .with_context(|| format!("failed to read metadata of {}", path.display()))?;
+            Ok(FileTimes::new()
+                .set_modified(metadata.modified()?)
+                .set_accessed(metadata.accessed()?))
+        }
+        Source::Memory(file) => Ok(match file.modified {
+            Some(modified) => FileTimes::new().set_modified(modified.into()),
+            None => FileTimes::new(),
+        }),
+    }
+}
+
+/// Set the file times of `target` to `times`
+///
+/// Copies of read-only sources are read-only as well,
+/// so the file is opened for reading if it cannot be opened for writing.
+fn set_times(target: &Path, times: FileTimes) -> Result<()> {
+    File::options()
+        .write(true)
+        .open(target)
+        .or_else(|_| File::open(target))
+        .and_then(|file| file.set_times(times))
+        .with_context(|| format!("failed to set the file times of {}", target.display()))
+}
+
+/// Run the `post_copy_command` for the file copied to `target`, if configured
+///
+/// The command runs without a shell and the path is substituted into its words,
+/// so that the path is never interpreted, see [Config::post_copy_command].
+/// Failures are only logged, and nothing is run in a dry run.
+fn run_post_copy_command(target: &Path, config: &Config) {
+    let Some(template) = &config.post_copy_command else {
+        return;
+    };
+    if config.dry_run {
+        return;
+    }
+
+    // The command was checked with the configuration
+    let words = command_words(template).unwrap_or_default();
+    let mut args = words.iter().map(|word| {
+        let mut arg = OsString::new();
+        for (i, part) in word.split("{path}").enumerate() {
+            if i > 0 {
+                arg.push(target);
+            }
+            arg.push(part);
+        }
+        arg
+    });
+    let Some(program) = args.next() else {
+        return;
+    };
+
+    match Command::new(program).args(args).status() {
+        Ok(status) if status.success() => {
+            debug!("Ran post-copy command for {}", target.display());
+        }
+        Ok(status) => warn!(
+            "Post-copy command for {} failed with {status}",
+            target.display()
+        ),
+        Err(e) => warn!(
+            "Failed to run post-copy command for {}: {e}",
+            target.display()
+        ),
+    }
+}
+
+/// Split the `command` into words at whitespace
+///
+/// Single or double quotes group words with whitespace and are removed.
+/// There are no escapes, a quote of the other kind is taken literally within quotes.
+fn command_words(command: &str) -> Result<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut quote = None;
+    for c in command.chars() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => word.get_or_insert_default().push(c),
+            None if c == '\'' || c == '"' => {
+                quote = Some(c);
+                word.get_or_insert_default();
+            }
+            None if c.is_whitespace() => words.extend(word.take()),
+            None => word.get_or_insert_default().push(c),
+        }
+    }
+    if let Some(q) = quote {
+        bail!("unterminated {q} quote in command '{command}'");
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+/// Sidecar files next to the media files, listed once per directory
+///
+/// Sidecars are keyed by their path without extension,
+/// which is the path of their media file without or with its extension.
+#[derive(Default)]
+struct Sidecars(HashMap<PathBuf, Vec<PathBuf>>);
+
+impl Sidecars {
+    /// List the sidecars with the `include_sidecars` extensions in the directories of `files`
+    fn new(files: &[PathBuf], config: &Config) -> Result<Self> {
+        let mut sidecars = Self::default();
+        if config.include_sidecars.is_empty() {
+            return Ok(sidecars);
+        }
+        let extensions = ExtensionSet::new(&config.include_sidecars)?;
+
+        let dirs: HashSet<_> = files.iter().filter_map(|path| path.parent()).collect();
+        for dir in dirs {
+            let listed = if dir.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                dir
+            };
+            let entries = match fs::read_dir(listed) {
+                Ok(entries) => entries,
+                Err(e) => {
+                    warn!("Failed to list sidecars in {}: {e}", listed.display());
+                    continue;
+                }
+            };
+            for sidecar in entries.filter_map(|entry| Some(entry.ok()?.path())) {
+                if extensions.matches(&sidecar) {
+                    let key = dir.join(sidecar.file_stem().unwrap_or_default());
+                    sidecars.0.entry(key).or_default().push(sidecar);
+                }
+            }
+        }
+
+        Ok(sidecars)
+    }
+
+    /// Sidecars of the media file at `path`, named after its stem or its full name
+    fn of(&self, path: &Path) -> Vec<PathBuf> {
+        let mut keys = vec![path.with_extension(""), path.to_owned()];
+        keys.dedup();
+        keys.iter()
+            .filter_map(|key| self.0.get(key))
+            .flatten()
+            .cloned()
+            .collect()
+    }
+}
+
+/// Targets next to `target` of the `sidecars` of the media file at `path`
+///
+/// Sidecars whose target already exists are left out.
+fn sidecar_targets(path: &Path, sidecars: &[PathBuf], target: &Path) -> Vec<(PathBuf, PathBuf)> {
+    let name = path.file_name().unwrap_or_default();
+    let target_name = target.file_name().unwrap_or_default();
+    let target_stem = target.file_stem().unwrap_or_default();
+
+    let mut targets = Vec::new();
+    for sidecar in sidecars {
+        // Sidecars are named after the stem or the full name of the media file
+        let mut target_sidecar_name = if sidecar.file_stem() == Some(name) {
+            target_name.to_owned()
+        } else {
+            target_stem.to_owned()
+        };
+        target_sidecar_name.push(".");
+        target_sidecar_name.push(sidecar.extension().unwrap_or_default());
+
+        let sidecar_target = target.with_file_name(target_sidecar_name);
+        if sidecar_target.exists() {
+            debug!(
+                "Not transferring sidecar {} to existing {}",
+                sidecar.display(),
+                sidecar_target.display()
+            );
+            continue;
+        }
+        targets.push((sidecar.clone(), sidecar_target));
+    }
+
+    targets
+}
+
+/// Result of checking a media file against the existing files
+enum Check {
+    /// The file should be skipped as duplicate of the existing file
+    Skip(SkipReason, PathBuf),
+    /// The file should be imported
+    Import {
+        /// Existing file of which this file is a higher-quality version
+        replaces: Option<PathBuf>,
+    },
+}
+
+/// Compute the perceptual hash of the image at `path` if configured for its extension
+///
+/// A `cached` hash of the unchanged file is returned instead of computing it again.
+fn perceptual_hash(path: &Path, cached: Option<u64>, config: &Config) -> Option<u64> {
+    config.perceptual_hash_threshold?;
+    let ext = path.extension()?;
+    if !config
+        .perceptual_hash_extensions
+        .iter()
+        .any(|e| ext.eq_ignore_ascii_case(trim_dot(e)))
+    {
+        return None;
+    }
+    if cached.is_some() {
+        return cached;
+    }
+
+    #[cfg(feature = "perceptual-hash")]
+    let hash = crate::phash::dhash(path)
+        .inspect_err(|e| {
+            warn!(
+                "Failed to compute perceptual hash of {}: {e:#}",
+                path.display()
+            )
+        })
+        .ok();
+    #[cfg(not(feature = "perceptual-hash"))]
+    let hash = None;
+
+    hash
+}
+
+/// Check if the file at `path` of size `file_size` duplicates one of the `existing` files
+///
+/// Versions of a file are matched by the resolved creation timestamp returned by `created`,
+/// which is only called if needed.
+fn check_existing(
+    source: Source,
+    file_size: u64,
+    created: impl Fn() -> Option<DateTime<FixedOffset>>,
+    existing: &MediaFiles,
+    config: &Config,
+    stats: &mut Statistics,
+) -> Result<Check> {
+    let path = source.path();
+
+    // Any file with the same content is a duplicate, regardless of name and date
+    if config.strict_content_dedup {
+        let hash = source.content_hash(config.content_hash_algo)?;
+        if let Some(existing) = existing.content_map.get(&hash).and_then(|v| v.first()) {
+            debug!(
+                "Identified {} as duplicate of {} (same content)",
+                path.display(),
+                existing.path.display(),
+            );
+            return Ok(Check::Skip(SkipReason::SameContent, existing.path.clone()));
+        }
+    }
+
+    // Check for a match with an existing file
+    if let Some(existing) = path.file_name().and_then(|n| existing.by_name(n)) {
+        stats.name_existing += 1;
+
+        // We have at least one file with the same filename.
+        // In the majority of cases, this is the exact same file.
+        // Reading the file size is cheap,
+        // reading the exif create date is more expensive via the slow connection.
+
+        // We check first if there is an exact size match and skip the duplicate in this case.
+        let same_size: Vec<_> = existing.iter().filter(|e| e.size == file_size).collect();
+        if !same_size.is_empty() && config.confirm_by_content {
+            // Different photos can have the same name and size by coincidence
+            let hash = source.content_hash(config.content_hash_algo)?;
+            for existing in &same_size {
+                if content_hash(&existing.path, config.content_hash_algo)? == hash {
+                    debug!(
+                        "Identified {} as duplicate of {} (same name and content)",
+                        path.display(),
+                        existing.path.display(),
+                    );
+                    return Ok(Check::Skip(SkipReason::Duplicate, existing.path.clone()));
+                }
+            }
+            info!(
+                "Importing {} with the same name and size as {} but different content",
+                path.display(),
+                same_size[0].path.display(),
+            );
+            return Ok(Check::Import { replaces: None });
+        } else if let Some(existing) = same_size.first() {
+            debug!(
+                "Identified {} as duplicate of {} (same name, both {file_size} bytes)",
+                path.display(),
+                existing.path.display(),
+            );
+            return Ok(Check::Skip(SkipReason::Duplicate, existing.path.clone()));
+        }
+
+        // Copies rotated with `auto_rotate` differ in size from their sources
+        #[cfg(feature = "auto-rotate")]
+        if config.auto_rotate
+            && ExtensionSet(HashSet::from(["jpg".into(), "jpeg".into()])).matches(path)
+            && let Some(rotated_size) = source
+                .rotated()
+                .map(|rotated| rotated.map(|data| data.len() as u64))
+                .unwrap_or_else(|e| {
+                    debug!("Failed to rotate {}: {e:#}", path.display());
+                    None
+                })
+            && let Some(existing) = existing.iter().find(|e| e.size == rotated_size)
+        {
+            debug!(
+                "Identified {} as duplicate of {} (same name, rotated to {rotated_size} bytes)",
+                path.display(),
+                existing.path.display(),
+            );
+            return Ok(Check::Skip(SkipReason::Duplicate, existing.path.clone()));
+        }
+
+        // There is no size match, we have to check the creation date
+        // to identify if this is the same media file with differing quality.
+        let created = created().unwrap_or_default();
+
+        // Copies of undated files grow by the exif data written with `backfill_exif`.
+        // The written date has no fraction of a second.
+        #[cfg(feature = "backfill-exif")]
+        if config.backfill_exif
+            && let Some(existing) = existing.iter().find(|e| {
+                e.size == file_size + crate::backfill::added_size()
+                    && e.created.timestamp() == created.timestamp()
+            })
+        {
+            debug!(
+                "Identified {} as duplicate of {} (same name and date, exif backfilled)",
+                path.display(),
+                existing.path.display(),
+            );
+            return Ok(Check::Skip(SkipReason::Duplicate, existing.path.clone()));
+        }
+
+        if let Some(existing) = existing
+            .iter()
+            .find(|e| same_created(&e.created, &created, config))
+        {
+            debug!(
+                "File {} ({file_size} bytes) is already found at {} ({} bytes)",
+                path.display(),
+                existing.path.display(),
+                existing.size,
+            );
+
+            if !is_better_version(source, file_size, existing, config) {
+                // The new version is of lower or equal quality.
+                debug!(
+                    "Skipping duplicate / lower-quality version of {}",
+                    path.display()
+                );
+                return Ok(Check::Skip(SkipReason::LowerQuality, existing.path.clone()));
+            }
+
+            return Ok(Check::Import {
+                replaces: Some(existing.path.clone()),
+            });
+        }
+
+        // Same day and name but a different time are most likely distinct shots.
+        if let Some(existing) = existing
+            .iter()
+            .find(|e| e.created.date_naive() == created.date_naive())
+        {
+            info!(
+                "Importing {} created at {created} as distinct from {} created at {}",
+                path.display(),
+                existing.path.display(),
+                existing.created,
+            );
+            stats.ambiguous.push(AmbiguousMatch {
+                source: path.to_owned(),
+                created,
+                existing: existing.path.clone(),
+                existing_created: existing.created,
+            });
+        }
+    } else if config.dedup_by_content && !config.strict_content_dedup {
+        // No file with the same name exists, but it may have been renamed.
+        let hash = source.content_hash(config.content_hash_algo)?;
+        if let Some(existing) = existing.content_map.get(&hash).and_then(|v| v.first()) {
+            debug!(
+                "Identified {} as duplicate of {} (same content)",
+                path.display(),
+                existing.path.display(),
+            );
+            return Ok(Check::Skip(SkipReason::SameContent, existing.path.clone()));
+        }
+    }
+
+    // With ranked extensions, versions with another extension are compared as well
+    if let QualityHeuristic::PreferExtension(ranking) = &config.quality_heuristic
+        && let Some(stem) = path.file_stem()
+        && let Some(created) = created()
+    {
+        let own_extension = path.extension().unwrap_or_default();
+        let version = ranking
+            .iter()
+            .map(|ext| trim_dot(ext))
+            .filter(|ext| !own_extension.eq_ignore_ascii_case(ext))
+            .filter_map(|ext| {
+                let mut name = stem.to_owned();
+                name.push(".");
+                name.push(ext);
+                existing.by_name(&name)
+            })
+            .flatten()
+            .find(|e| same_created(&e.created, &created, config));
+
+        if let Some(version) = version {
+            if !is_better_version(source, file_size, version, config) {
+                debug!(
+                    "Skipping {} as lower-quality version of {}",
+                    path.display(),
+                    version.path.display()
+                );
+                return Ok(Check::Skip(SkipReason::LowerQuality, version.path.clone()));
+            }
+            return Ok(Check::Import {
+                replaces: Some(version.path.clone()),
+            });
+        }
+    }
+
+    if let Some(threshold) = config.perceptual_hash_threshold
+        && let Source::File(path) = source
+        && let Some(hash) = perceptual_hash(path, None, config)
+        && let Some((_, existing)) = existing
+            .perceptual
+            .iter()
+            .find(|(h, _)| (h ^ hash).count_ones() < threshold)
+    {
+        debug!(
+            "Identified {} as near-duplicate of {} (perceptual hash)",
+            path.display(),
+            existing.path.display(),
+        );
+        return Ok(Check::Skip(
+            SkipReason::NearDuplicate,
+            existing.path.clone(),
+        ));
+    }
+
+    Ok(Check::Import { replaces: None })
+}
+
+/// Target path for a media file
+enum TargetPath {
+    /// Free path to place the file at
+    Free(PathBuf),
+    /// Path of an existing file with the same content
+    SameContent(PathBuf),
+}
+
+/// Find a path based on `target` where `source` can be placed without overwriting a different file
+///
+/// On a collision, a suffix is appended to the file stem by the `collision_strategy`,
+/// e.g. `IMG_1234_1.jpg`. Hash suffixes are followed by a number if they collide as well.
+/// Paths in `reserved` are skipped like existing files.
+/// Contents are compared by hashing them with the `content_hash_algo`.
+fn unique_target_path(
+    source: Source,
+    target: PathBuf,
+    reserved: &HashSet<PathBuf>,
+    config: &Config,
+) -> Result<TargetPath> {
+    let algorithm = config.content_hash_algo;
+    let stem = target.file_stem().unwrap_or_default().to_owned();
+    let extension = target.extension().map(ToOwned::to_owned);
+
+    let mut candidate = target.clone();
+    let mut suffix = 0usize;
+    let mut hash_suffix: Option<String> = None;
+    while reserved.contains(&candidate) || candidate.exists() {
+        if !reserved.contains(&candidate)
+            && source.size()? == file_size(&candidate)?
+            && source.content_hash(algorithm)? == content_hash(&candidate, algorithm)?
+        {
+            return Ok(TargetPath::SameContent(candidate));
+        }
+
+        suffix += 1;
+        let mut name = stem.clone();
+        match config.collision_strategy {
+            CollisionStrategy::Counter => name.push(format!("_{suffix}")),
+            CollisionStrategy::HashSuffix => {
+                if hash_suffix.is_none() {
+                    let hash = source.content_hash(HashAlgorithm::Blake3)?;
+                    hash_suffix = Some(hash[..4].iter().map(|b| format!("{b:02x}")).collect());
+                }
+                name.push("_");
+                name.push(hash_suffix.as_deref().unwrap_or_default());
+                if suffix > 1 {
+                    name.push(format!("_{}", suffix - 1));
+                }
+            }
+        }
+        if let Some(extension) = &extension {
+            name.push(".");
+            name.push(extension);
+        }
+        candidate = target.with_file_name(name);
+    }
+
+    if suffix > 0 {
+        debug!(
+            "Resolved name collision of {} to {}",
+            target.display(),
+            candidate.display()
+        );
+    }
+
+    Ok(TargetPath::Free(candidate))
+}
+
+/// Check if `source` of size `file_size` is a better version of `existing`
+///
+/// The versions are compared by the `quality_heuristic`, keeping the existing file on ties.
+/// Ties of ranked extensions are decided by size.
+fn is_better_version(
+    source: Source,
+    file_size: u64,
+    existing: &MediaFile,
+    config: &Config,
+) -> bool {
+    match &config.quality_heuristic {
+        QualityHeuristic::LargerSize => file_size > existing.size,
+        QualityHeuristic::NewerMtime => source.modified() > modified_time(&existing.path),
+        QualityHeuristic::PreferExtension(ranking) => {
+            let rank = |path: &Path| {
+                path.extension().and_then(|ext| {
+                    ranking
+                        .iter()
+                        .position(|e| ext.eq_ignore_ascii_case(trim_dot(e)))
+                })
+            };
+            // Unranked extensions come last
+            match (rank(source.path()), rank(&existing.path)) {
+                (Some(a), Some(b)) if a != b => a < b,
+                (Some(_), None) => true,
+                (None, Some(_)) => false,
+                _ => file_size > existing.size,
+            }
+        }
+    }
+}
+
+/// Check if `a` and `b` are the same path after resolving symbolic links and `..`
+///
+/// Copying a file onto itself could truncate it.
+fn is_same_file(a: &Path, b: &Path) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Check if the files at `a` and `b` have the same size and content
+fn same_content(a: &Path, b: &Path, algorithm: HashAlgorithm) -> Result<bool> {
+    Ok(
+        file_size(a)? == file_size(b)?
+            && content_hash(a, algorithm)? == content_hash(b, algorithm)?,
+    )
+}
+
+/// Copy the file at `source` to `target`
+///
+/// The copy is written atomically, see [write_atomically].
+/// With `verify_copies`, the copy is compared to the source by size and content hash.
+/// A mismatching copy is retried once before failing.
+fn copy_file(
+    source: &Path,
+    target: &Path,
+    config: &Config,
+    throttle: Option<&Throttle>,
+) -> Result<()> {
+    write_atomically(target, |temp| {
+        if !config.verify_copies {
+            copy_retrying(source, temp, config, throttle)?;
+            return Ok(());
+        }
+
+        for attempt in 1..=2 {
+            copy_retrying(source, temp, config, throttle)?;
+            if same_content(source, temp, config.content_hash_algo)? {
+                debug!(
+                    "Verified copy of {} to {}",
+                    source.display(),
+                    target.display()
+                );
+                return Ok(());
+            }
+
+            warn!(
+                "Copy of {} to {} differs from the source (attempt {attempt})",
+                source.display(),
+                target.display()
+            );
+        }
+
+        bail!(
+            "failed to verify copy of {} to {}",
+            source.display(),
+            target.display()
+        )
+    })
+}
+
+/// Write the media file `file` in memory to `target`
+///
+/// The file is written atomically, see [write_atomically].
+/// With `verify_copies`, the written file is compared to `file` by content hash.
+fn write_file(file: &MemoryFile, target: &Path, config: &Config) -> Result<()> {
+    write_atomically(target, |temp| {
+        fs::write(temp, &file.data)
+            .with_context(|| format!("failed to write {}", target.display()))?;
+        let algorithm = config.content_hash_algo;
+        if config.verify_copies
+            && content_hash(temp, algorithm)? != Source::Memory(file).content_hash(algorithm)?
+        {
+            bail!(
+                "failed to verify {} written from {}",
+                target.display(),
+                file.path.display()
+            );
+        }
+        Ok(())
+    })
+}
+
+/// Create `target` by writing a temporary file with `write` and renaming it into place
+///
+/// The temporary file is named like `.IMG_0001.jpg.tmp` in the directory of `target`,
+/// so that no partial target remains if the process dies while writing.
+/// It is removed if writing or renaming fails.
+fn write_atomically(target: &Path, write: impl FnOnce(&Path) -> Result<()>) -> Result<()> {
+    let mut temp_name = OsString::from(".");
+    temp_name.push(target.file_name().unwrap_or_default());
+    temp_name.push(".tmp");
+    let temp = target.with_file_name(temp_name);
+
+    let result = write(&temp).and_then(|()| {
+        fs::rename(&temp, target).with_context(|| {
+            format!(
+                "failed to rename {} to {}",
+                temp.display(),
+                target.display()
+            )
+        })
+    });
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
+
+/// Initial delay before retrying a copy, doubled on each further attempt
+const RETRY_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Copy the file at `source` to `target`, retrying transient errors up to `copy_retries` times
+///
+/// The copy is limited to the rate of `throttle`, if given.
+fn copy_retrying(
+    source: &Path,
+    target: &Path,
+    config: &Config,
+    throttle: Option<&Throttle>,
+) -> io::Result<u64> {
+    let mut attempt = 0;
+    loop {
+        let result = match throttle {
+            Some(throttle) => throttle.copy(source, target),
+            None => fs::copy(source, target),
+        };
+        match result {
+            Err(e) if attempt < config.copy_retries && is_transient(&e, config) => {
+                let delay = RETRY_BACKOFF.saturating_mul(2u32.saturating_pow(attempt));
+                warn!(
+                    "Failed to copy {} to {}: {e}, retrying in {delay:?}",
+                    source.display(),
+                    target.display()
+                );
+                thread::sleep(delay);
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Check if `error` is transient and the operation worth retrying
+fn is_transient(error: &io::Error, config: &Config) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock
+    ) || error
+        .raw_os_error()
+        .is_some_and(|code| config.retry_os_errors.contains(&code))
+}
+
+/// Move the file at `source` to `target`
+///
+/// Falls back to copying and removing the source if both are on different filesystems,
+/// limited to the rate of `throttle`, if given.
+/// A failure to remove the source after a successful copy is only logged.
+/// Read-only sources are left in place unless `force_move_readonly` is set.
+fn move_file(
+    source: &Path,
+    target: &Path,
+    config: &Config,
+    throttle: Option<&Throttle>,
+) -> Result<()> {
+    match fs::rename(source, target) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            copy_file(source, target, config, throttle)?;
+            remove_moved(source, target, config);
+            Ok(())
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Remove the `source` file after copying it to `target`
+///
+/// Only if the removal fails for a read-only `source`, e.g. on Windows,
+/// the read-only attribute is cleared with `force_move_readonly`.
+/// Otherwise the `source` is left in place with a warning.
+fn remove_moved(source: &Path, target: &Path, config: &Config) {
+    let mut result = fs::remove_file(source);
+    if let Err(e) = &result
+        && e.kind() == io::ErrorKind::PermissionDenied
+        && fs::metadata(source).is_ok_and(|m| m.permissions().readonly())
+    {
+        if !config.force_move_readonly {
+            warn!(
+                "Leaving read-only {} in place after copying it to {}",
+                source.display(),
+                target.display()
+            );
+            return;
+        }
+        result = clear_readonly(source).and_then(|()| fs::remove_file(source));
+    }
+    if let Err(e) = result {
+        warn!(
+            "Failed to remove {} after copying it to {}: {e}",
+            source.display(),
+            target.display()
+        );
+    }
+}
+
+/// Make the file at `path` writable for its owner
+fn clear_readonly(path: &Path) -> io::Result<()> {
+    let mut permissions = fs::metadata(path)?.permissions();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        permissions.set_mode(permissions.mode() | 0o200);
+    }
+    #[cfg(windows)]
+    permissions.set_readonly(false);
+    fs::set_permissions(path, permissions)
+}
+
+/// Find the media files in `paths` with the extensions and excludes of `config`
+pub fn find_media_files(
+    paths: &[String],
+    config: &Config,
+) -> Result<Vec<PathBuf>, MediaToolsError> {
+    Ok(MediaWalk::new(config)?
+        .find_media_files(paths, &[])
+        .collect())
+}
+
+/// Number and total size of media files, see [count_media_files]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MediaCount {
+    /// Number of media files
+    pub files: usize,
+    /// Total size of the media files in bytes
+    pub bytes: u64,
+}
+
+impl fmt::Display for MediaCount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} files, {}", self.files, human_bytes(self.bytes))
+    }
+}
+
+/// Count the media files in the `search_paths` of `config` and sum up their sizes
+///
+/// The search paths are walked like for an import, skipping the output paths,
+/// but no file is parsed, so that this is fast even for large trees.
+/// Files of which the size cannot be read are logged and counted without a size.
+pub fn count_media_files(config: &Config) -> Result<MediaCount, MediaToolsError> {
+    let outputs = config.canonical_outputs();
+    let mut count = MediaCount::default();
+    for path in MediaWalk::new(config)?.find_media_files(&config.search_paths, &outputs) {
+        count.files += 1;
+        match file_size(&path) {
+            Ok(size) => count.bytes += size,
+            Err(e) => warn!("Failed to get size of {}: {e}", path.display()),
+        }
+    }
+    Ok(count)
+}
+
+/// Find and index the media files in `paths` with the settings of `config`
+///
+/// Files are parsed in parallel, using at most `index_threads` threads if configured.
+/// Files which fail to parse are logged and left out.
+pub fn index_media_files(
+    paths: &[String],
+    config: &Config,
+) -> Result<Vec<MediaFile>, MediaToolsError> {
+    let files = find_media_files(paths, config)?;
+    with_index_threads(config, || {
+        files
+            .par_iter()
+            .filter_map(|p| {
+                MediaFile::try_from_path(p, config)
+                    .inspect_err(|e| warn!("Failed to parse {}: {e:#}", p.display()))
+                    .ok()
+            })
+            .collect()
+    })
+    .map_err(Into::into)
+}
+
+/// Resolve the target path of the media file at `source_path` with the settings of `config`
+///
+/// The file is dated and placed like during an import,
+/// but without checking for duplicates or name collisions and without copying it.
+/// Raw and Live Photo siblings and bursts are not taken into account,
+/// since they depend on the other files of an import.
+pub fn resolve_target(config: &Config, source_path: &Path) -> Result<PathBuf, MediaToolsError> {
+    let created = creation_timestamp(source_path, config);
+    let directory = target_directory(source_path, created.as_ref(), config)?;
+    let file_name = target_file_name(
+        source_path,
+        &created.unwrap_or_default(),
+        config.filename_template.as_deref(),
+        config.bucket_timezone,
+    )?;
+    Ok(directory.join(file_name))
+}
+
+/// Decision on importing a media file, see [plan_import]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImportDecision {
+    /// Copy the file to `target`, replacing the lower-quality file `replaces` if any
+    Copy {
+        /// Path of the media file in the search paths
+        source: PathBuf,
+        /// Path to copy the file to
+        target: PathBuf,
+        /// Resolved creation timestamp, `None` for undated files
+        created: Option<DateTime<FixedOffset>>,
+        /// Existing file of which this file is a higher-quality version
+        replaces: Option<PathBuf>,
+    },
+    /// Copy the file to `target`, although it has the same name and day as the `existing` file
+    ///
+    /// Both files have different creation times, see [AmbiguousMatch].
+    Conflict {
+        /// Path of the media file in the search paths
+        source: PathBuf,
+        /// Path to copy the file to
+        target: PathBuf,
+        /// Resolved creation timestamp, `None` for undated files
+        created: Option<DateTime<FixedOffset>>,
+        /// Path of the existing file
+        existing: PathBuf,
+    },
+    /// Skip the file for `reason`, duplicating the `existing` file if any
+    Skip {
+        /// Path of the media file in the search paths
+        source: PathBuf,
+        /// Reason to skip the file
+        reason: SkipReason,
+        /// Path of the existing file the media file duplicates
+        existing: Option<PathBuf>,
+    },
+}
+
+impl ImportDecision {
+    /// Path of the media file in the search paths
+    pub fn source(&self) -> &Path {
+        match self {
+            Self::Copy { source, .. }
+            | Self::Conflict { source, .. }
+            | Self::Skip { source, .. } => source,
+        }
+    }
+
+    /// Carry out the decision with the settings of `config`
+    ///
+    /// Files to copy are transferred like during an import, including sidecars and hooks,
+    /// while skipped files are left alone.
+    /// The manifest is not written and replaced files are not pruned.
+    /// Returns the target path if the file was copied, or would be in a dry run.
+    pub fn apply(&self, config: &Config) -> Result<Option<PathBuf>, MediaToolsError> {
+        let (source, target, created, replaces) = match self {
+            Self::Copy {
+                source,
+                target,
+                created,
+                replaces,
+            } => (source, target, created, replaces.clone()),
+            Self::Conflict {
+                source,
+                target,
+                created,
+                ..
+            } => (source, target, created, None),
+            Self::Skip { .. } => return Ok(None),
+        };
+
+        let sidecars = Sidecars::new(std::slice::from_ref(source), config)?.of(source);
+        let source = Source::File(source);
+        if let Some((reason, _)) = check_target(source, target, config)? {
+            debug!("Not applying {} for {reason:?}", target.display());
+            return Ok(None);
+        }
+        let copy = PlannedCopy {
+            target: target.clone(),
+            created: *created,
+            replaces,
+            directory: String::new(),
+            sidecars,
+            device: None,
+        };
+        if config.dry_run {
+            report_new_directory(target, &mut HashSet::new());
+        }
+        let throttle = config.max_read_bytes_per_sec.map(Throttle::new);
+        transfer(source, &copy, config, throttle.as_ref())?;
+        run_post_copy_command(target, config);
+        Ok(Some(copy.target))
+    }
+}
+
+/// Iterator over the [ImportDecision]s of an import, see [plan_import]
+pub struct ImportPlan {
+    /// Configuration to decide by, continuing after previous imports with `auto_since`
+    config: Config,
+    /// Indexed existing files
+    existing: MediaFiles,
+    /// Media files in the search paths yet to decide on
+    files: std::vec::IntoIter<PathBuf>,
+    /// Map from media files to the sibling they are dated by
+    date_siblings: HashMap<PathBuf, PathBuf>,
+    /// Targets of previous decisions and burst directories
+    plan: TargetPlan,
+    /// Statistics of the decisions, to detect ambiguous matches
+    stats: Statistics,
+}
+
+impl ImportPlan {
+    /// Decide on importing the media file at `path`
+    fn decide(&mut self, path: PathBuf) -> Result<ImportDecision> {
+        let source = Source::File(&path);
+        let date_source = Source::File(self.date_siblings.get(&path).unwrap_or(&path));
+        let ambiguous = self.stats.ambiguous.len();
+        let decision = decide(
+            source,
+            source.size()?,
+            date_source,
+            &self.existing,
+            &self.plan,
+            &self.config,
+            &mut self.stats,
+        )?;
+
+        Ok(match decision {
+            Decision::Skip(reason, existing) => ImportDecision::Skip {
+                source: path,
+                reason,
+                existing,
+            },
+            Decision::Copy(copy) => {
+                self.plan.reserved.insert(copy.target.clone());
+                match self.stats.ambiguous.get(ambiguous) {
+                    Some(matched) => ImportDecision::Conflict {
+                        source: path,
+                        target: copy.target,
+                        created: copy.created,
+                        existing: matched.existing.clone(),
+                    },
+                    None => ImportDecision::Copy {
+                        source: path,
+                        target: copy.target,
+                        created: copy.created,
+                        replaces: copy.replaces,
+                    },
+                }
+            }
+        })
+    }
+}
+
+impl Iterator for ImportPlan {
+    type Item = Result<ImportDecision, MediaToolsError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let path = self.files.next()?;
+        Some(self.decide(path).map_err(Into::into))
+    }
+}
+
+/// Index the existing files and return an iterator deciding on the media files one by one
+///
+/// The decisions are made like during an import, but nothing is copied
+/// until a decision is applied with [ImportDecision::apply].
+/// Targets of earlier decisions are taken into account by later ones,
+/// whether they are applied or not.
+/// Entries of zip archives are not decided on.
+pub fn plan_import(config: &Config) -> Result<ImportPlan, MediaToolsError> {
+    config.validate()?;
+    let raw_extensions = ExtensionSet::new(&config.raw_extensions)?;
+    let walk = MediaWalk::new(config)?;
+    let (existing, config) = index_existing(config, &walk, None)?;
+    let config = config.into_owned();
+
+    let files: Vec<PathBuf> = walk
+        .find_media_files(&config.search_paths, &config.canonical_outputs())
+        .collect();
+    let date_siblings = date_siblings(&files, &raw_extensions, &config);
+    let mut plan = TargetPlan {
+        sidecars: Sidecars::new(&files, &config)?,
+        ..Default::default()
+    };
+    if let Some(window) = config.burst_window_secs {
+        plan.metadata = creation_metadata_of(&files, &date_siblings, &config);
+        plan.bursts = burst_directories(&plan.metadata, window, &config);
+    }
+
+    Ok(ImportPlan {
+        config,
+        existing,
+        files: files.into_iter(),
+        date_siblings,
+        plan,
+        stats: Statistics::default(),
+    })
+}
+
+/// Run `f` in a thread pool limited to `index_threads` if configured
+fn with_index_threads<T: Send>(config: &Config, f: impl FnOnce() -> T + Send) -> Result<T> {
+    match config.index_threads {
+        Some(threads) => Ok(ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .context("failed to build index thread pool")?
+            .install(f)),
+        None => Ok(f()),
+    }
+}
+
+/// Set of existing [MediaFile]s
+struct MediaFiles {
+    /// Map from lowercased filenames to vectors of indexed files by this name
+    ///
+    /// Look up names with [MediaFiles::by_name] to ignore their case.
+    name_map: HashMap<OsString, Vec<MediaFile>>,
+    /// Map from content hashes to vectors of indexed files with this content
+    ///
+    /// Only populated when deduplicating by content.
+    content_map: HashMap<Vec<u8>, Vec<MediaFile>>,
+    /// Perceptual hashes of indexed images
+    ///
+    /// Only populated when detecting near-duplicates by perceptual hash.
+    perceptual: Vec<(u64, MediaFile)>,
+    /// Files which failed to parse, with the reason
+    ///
+    /// Files with unparseable exif data are indexed nevertheless.
+    failed: Vec<(PathBuf, anyhow::Error)>,
+    /// Number of files parsed instead of taken from the `index_cache`
+    parsed: usize,
+}
+
+impl MediaFiles {
+    /// Index the media files in `paths`
+    ///
+    /// Files are parsed in parallel, using at most `index_threads` threads if configured.
+    /// With an `index_cache`, unchanged files are taken from the cache instead.
+    /// Once the `cancel_flag` is set, the remaining files are not indexed
+    /// and the cache is left unchanged.
+    fn from_paths(
+        paths: &[String],
+        walk: &MediaWalk,
+        config: &Config,
+        cancel_flag: Option<&AtomicBool>,
+    ) -> Result<Self> {
+        let _span = info_span!("index").entered();
+        let cancelled = || cancel_flag.is_some_and(|c| c.load(Ordering::Relaxed));
+        let files: Vec<PathBuf> = walk
+            .find_media_files(paths, &[])
+            .take_while(|_| !cancelled())
+            .collect();
+        let parsed = AtomicUsize::new(0);
+        let cache = config
+            .index_cache
+            .as_deref()
+            .map(|p| IndexCache::load(Path::new(p), config))
+            .unwrap_or_default();
+
+        type Indexed = (MediaFile, Option<SystemTime>, Option<Vec<u8>>, Option<u64>);
+        type Parsed = (Indexed, Option<anyhow::Error>);
+        let index = || -> Vec<Result<Parsed, (PathBuf, anyhow::Error)>> {
+            files
+                .par_iter()
+                .filter(|_| !cancelled())
+                .map(|p| {
+                    let modified = fs::metadata(p).and_then(|m| m.modified()).ok();
+                    if let Some(entry) = modified.and_then(|m| cache.get(p, m)) {
+                        return Ok((entry.file.clone(), modified, Some(entry), None));
+                    }
+
+                    // Files with unparseable exif data are indexed, but reported as failed
+                    parsed.fetch_add(1, Ordering::Relaxed);
+                    MediaFile::index(p, config)
+                        .inspect(|(_, error)| {
+                            if let Some(e) = error {
+                                warn!("Failed to parse {}: {e:#}", p.display());
+                            }
+                        })
+                        .inspect_err(|e| warn!("Failed to parse {}: {e:#}", p.display()))
+                        .map(|(file, error)| (file, modified, None, error))
+                        .map_err(|e| (p.clone(), e))
+                })
+                .map(|result| {
+                    let (file, modified, cached, error) = result?;
+                    let algorithm = config.content_hash_algo;
+                    let hash = if config.dedup_by_content || config.strict_content_dedup {
+                        match cached.and_then(|e| e.content_hash(algorithm)) {
+                            Some(hash) => Some(hash),
+                            None => content_hash(&file.path, algorithm)
+                                .inspect_err(|e| {
+                                    warn!("Failed to hash {}: {e:#}", file.path.display())
+                                })
+                                .ok(),
+                        }
+                    } else {
+                        None
+                    };
+                    let perceptual =
+                        perceptual_hash(&file.path, cached.and_then(|e| e.perceptual_hash), config);
+                    Ok(((file, modified, hash, perceptual), error))
+                })
+                .collect()
+        };
+
+        let mut failed = Vec::new();
+        let mut indexed = Vec::new();
+        for result in with_index_threads(config, index)? {
+            match result {
+                Ok((file, Some(e))) => {
+                    failed.push((file.0.path.clone(), e));
+                    // Not cached, so that the error is reported again on the next run
+                    indexed.push((file, false));
+                }
+                Ok((file, None)) => indexed.push((file, true)),
+                Err(e) => failed.push(e),
+            }
+        }
+        if !failed.is_empty() {
+            warn!("{} existing files failed to parse", failed.len());
+        }
+
+        if let Some(cache_path) = &config.index_cache
+            && !cancelled()
+        {
+            let cache = IndexCache::from_files(
+                indexed.iter().filter(|(_, cache)| *cache).filter_map(
+                    |((file, modified, hash, perceptual), _)| {
+                        Some(CacheEntry {
+                            file: file.clone(),
+                            modified: (*modified)?,
+                            content_hash: hash.clone().map(|h| (config.content_hash_algo, h)),
+                            perceptual_hash: *perceptual,
+                        })
+                    },
+                ),
+                config,
+            );
+            if let Err(e) = cache.save(Path::new(cache_path)) {
+                warn!("Failed to write index cache {cache_path}: {e:#}");
+            }
+        }
+
+        let mut name_map = HashMap::new();
+        let mut content_map = HashMap::new();
+        let mut perceptual = Vec::new();
+
+        for ((existing, _, hash, perceptual_hash), _) in indexed {
+            if let Some(perceptual_hash) = perceptual_hash {
+                perceptual.push((perceptual_hash, existing.clone()));
+            }
+            if let Some(hash) = hash {
+                content_map
+                    .entry(hash)
+                    .or_insert_with(Vec::new)
+                    .push(existing.clone());
+            }
+
+            let key = existing
+                .path
+                .file_name()
+                .unwrap_or_default()
+                .to_ascii_lowercase();
+            name_map
+                .entry(key)
+                .and_modify(|v: &mut Vec<MediaFile>| v.push(existing.clone()))
+                .or_insert_with(|| vec![existing]);
+        }
+
+        Ok(Self {
+            name_map,
+            content_map,
+            perceptual,
+            failed,
+            parsed: parsed.into_inner(),
+        })
+    }
+
+    /// Latest exif creation timestamp of the indexed files below one of `paths`
+    ///
+    /// Timestamps further in the future than `max_future_skew_secs` are ignored,
+    /// so that a single corrupted date does not stop all further imports.
+    fn latest_below(
+        &self,
+        paths: &[impl AsRef<Path>],
+        config: &Config,
+    ) -> Option<DateTime<FixedOffset>> {
+        let latest = latest_plausible(config);
+        self.name_map
+            .values()
+            .flatten()
+            .filter(|file| paths.iter().any(|p| file.path.starts_with(p)))
+            .map(|file| file.created)
+            .filter(|created| *created != DateTime::<FixedOffset>::default())
+            .filter(|created| latest.is_none_or(|latest| *created <= latest))
+            .max()
+    }
+
+    /// Indexed files with the file name `name`, ignoring case
+    ///
+    /// E.g. `IMG_0001.JPG` and `img_0001.jpg` are considered the same name.
+    fn by_name(&self, name: &OsStr) -> Option<&Vec<MediaFile>> {
+        self.name_map.get(&name.to_ascii_lowercase())
+    }
+}
+
+/// Cache of indexed media files persisted between runs
+#[derive(Debug, Default)]
+struct IndexCache {
+    /// Fingerprint of the date settings the files were indexed with, see [date_settings]
+    date_settings: String,
+    /// Map from paths to cached entries
+    entries: HashMap<PathBuf, CacheEntry>,
+}
+
+/// Contents of an index cache file, with entries of type `E`
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheFile<E> {
+    /// Fingerprint of the date settings the entries were indexed with
+    date_settings: String,
+    entries: Vec<E>,
+}
+
+/// Cached [MediaFile] with the modification time it was indexed at
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheEntry {
+    #[serde(flatten)]
+    file: MediaFile,
+    modified: SystemTime,
+    /// Content hash of the file and the algorithm it was computed with
+    #[serde(default)]
+    content_hash: Option<(HashAlgorithm, Vec<u8>)>,
+    /// Perceptual hash of the file
+    #[serde(default)]
+    perceptual_hash: Option<u64>,
+}
+
+impl CacheEntry {
+    /// Cached content hash of the file, if it was computed with `algorithm`
+    fn content_hash(&self, algorithm: HashAlgorithm) -> Option<Vec<u8>> {
+        self.content_hash
+            .as_ref()
+            .filter(|(a, _)| *a == algorithm)
+            .map(|(_, hash)| hash.clone())
+    }
+}
+
+impl IndexCache {
+    /// Load the cache from `path`, starting empty if it cannot be read
+    ///
+    /// Caches indexed with other date settings than those of `config` are discarded,
+    /// as the dates of their entries may differ from those parsed now.
+    fn load(path: &Path, config: &Config) -> Self {
+        let date_settings = date_settings(config);
+        let file: Option<CacheFile<CacheEntry>> = match fs::read(path) {
+            Ok(data) => serde_json::from_slice(&data)
+                .inspect_err(|e| warn!("Ignoring invalid index cache {}: {e}", path.display()))
+                .ok(),
+            Err(e) => {
+                debug!("No index cache loaded from {}: {e}", path.display());
+                None
+            }
+        };
+        let entries = match file {
+            Some(file) if file.date_settings == date_settings => file.entries,
+            Some(_) => {
+                info!(
+                    "Discarding index cache {} of other date settings",
+                    path.display()
+                );
+                Vec::new()
+            }
+            None => Vec::new(),
+        };
+
+        Self::from_files(entries.into_iter(), config)
+    }
+
+    /// Build a cache from files indexed with the date settings of `config`,
+    /// their modification times and hashes
+    fn from_files(entries: impl Iterator<Item = CacheEntry>, config: &Config) -> Self {
+        Self {
+            date_settings: date_settings(config),
+            entries: entries.map(|e| (e.file.path.clone(), e)).collect(),
+        }
+    }
+
+    /// Get the cached entry of the file at `path` if it was not modified since
+    fn get(&self, path: &Path, modified: SystemTime) -> Option<&CacheEntry> {
+        self.entries.get(path).filter(|e| e.modified == modified)
+    }
+
+    /// Write the cache to `path`
+    fn save(&self, path: &Path) -> Result<()> {
+        let file = CacheFile {
+            date_settings: self.date_settings.clone(),
+            entries: self.entries.values().collect(),
+        };
+        fs::write(path, serde_json::to_vec(&file)?)?;
+        debug!(
+            "Wrote {} entries to index cache {}",
+            file.entries.len(),
+            path.display()
+        );
+        Ok(())
+    }
+}
+
+/// Fingerprint of the settings of `config` which the dates of indexed files depend on
+///
+/// These are the `date_tags`, `video_utc_offset`, `date_strategy` and `subsecond_precision`.
+fn date_settings(config: &Config) -> String {
+    let mut date_tags: Vec<_> = config.date_tags.iter().collect();
+    date_tags.sort_by_key(|(ext, _)| *ext);
+    format!(
+        "date_tags={date_tags:?} video_utc_offset={:?} date_strategy={:?} subsecond_precision={}",
+        config.video_utc_offset, config.date_strategy, config.subsecond_precision
+    )
+}
+
+/// Indexed media file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MediaFile {
+    /// Full path to file
+    pub path: PathBuf,
+    /// Exif creation timestamp, or the Unix epoch if the file has none
+    pub created: DateTime<FixedOffset>,
+    /// File size in bytes
+    pub size: u64,
+}
+
+impl MediaFile {
+    /// Try to read a file from the `path`, with the date tags of `config`
+    pub fn try_from_path(path: &Path, config: &Config) -> Result<Self, MediaToolsError> {
+        let (file, error) = Self::index(path, config)?;
+        if let Some(e) = error {
+            exif_created_logged(Err(e), path);
+        }
+        Ok(file)
+    }
+
+    /// Read a file from the `path` like [MediaFile::try_from_path]
+    ///
+    /// Files with unparseable exif data are still read, with the default creation timestamp,
+    /// so that they can be found by name and size. The parse error is returned alongside.
+    fn index(path: &Path, config: &Config) -> Result<(Self, Option<anyhow::Error>)> {
+        let size =
+            file_size(path).with_context(|| format!("failed to get size of {}", path.display()))?;
+        let (created, error) = match exif_created(path, config) {
+            Ok(created) => (exif_created_logged(Ok(created), path), None),
+            Err(e) => (None, Some(e)),
+        };
+        let file = Self {
+            path: path.to_owned(),
+            created: created.unwrap_or_default(),
+            size,
+        };
+        Ok((file, error))
+    }
+}
+
+/// Settings for walking paths in search of media files
+struct MediaWalk {
+    /// Extensions of media files
+    extensions: ExtensionSet,
+    /// Files and directories to exclude
+    exclude: GlobSet,
+    /// Follow symbolic links while walking
+    follow_symlinks: bool,
+    /// Sniff the type of files without extension
+    match_extensionless: bool,
+    /// Extensions which matched at least one file
+    matched: RefCell<HashSet<OsString>>,
+}
+
+impl MediaWalk {
+    /// Walk settings for the extensions, raw extensions and excludes of `config`
+    fn new(config: &Config) -> Result<Self> {
+        let mut extensions = ExtensionSet::new(&config.extensions)?;
+        extensions
+            .0
+            .extend(ExtensionSet::new(&config.raw_extensions)?.0);
+        for sidecar in ExtensionSet::new(&config.include_sidecars)?.0 {
+            extensions.0.remove(&sidecar);
+        }
+        Ok(Self {
+            extensions,
+            exclude: build_glob_set(&config.exclude)?,
+            follow_symlinks: config.follow_symlinks,
+            match_extensionless: config.match_extensionless,
+            matched: RefCell::default(),
+        })
+    }
+
+    /// Find media files in `search_paths` matching the extensions
+    ///
+    /// Excluded directories and the `skipped` canonical directories are not descended into.
+    /// The files of each search path are found in a stable order, sorted by file name
+    /// within each directory, so that repeated runs process them the same way.
+    fn find_media_files<'a>(
+        &'a self,
+        search_paths: &'a [String],
+        skipped: &'a [PathBuf],
+    ) -> impl Iterator<Item = PathBuf> + 'a {
+        self.find_media_files_with_extensions(search_paths, skipped)
+            .map(|(path, _)| path)
+    }
+
+    /// Find media files in `search_paths` like [MediaWalk::find_media_files] for an import
+    ///
+    /// The extensions of the files found are recorded for [MediaWalk::warn_unmatched_extensions],
+    /// unlike those of existing files, which do not show whether an extension is mistyped.
+    fn find_search_files<'a>(
+        &'a self,
+        search_paths: &'a [String],
+        skipped: &'a [PathBuf],
+    ) -> impl Iterator<Item = PathBuf> + 'a {
+        self.find_media_files_with_extensions(search_paths, skipped)
+            .map(|(path, ext)| {
+                self.matched.borrow_mut().insert(ext);
+                path
+            })
+    }
 
-/// Import configuration
-#[derive(Debug, Deserialize)]
-pub struct Config {
-    pub extensions: Vec<String>,
-    pub existing_paths: Vec<String>,
-    pub search_paths: Vec<String>,
-    pub output_path: String,
-}
+    /// Find media files like [MediaWalk::find_media_files], along with their matched extension
+    fn find_media_files_with_extensions<'a>(
+        &'a self,
+        search_paths: &'a [String],
+        skipped: &'a [PathBuf],
+    ) -> impl Iterator<Item = (PathBuf, OsString)> + 'a {
+        search_paths.iter().flat_map(move |s| {
+            let skipped = paths_below(Path::new(s), skipped);
+            WalkDir::new(s)
+                .follow_links(self.follow_symlinks)
+                .sort_by_file_name()
+                .into_iter()
+                .filter_entry(move |e| {
+                    !self.is_excluded(e.path()) && !skipped.iter().any(|p| e.path() == p)
+                })
+                .filter_map(|x| {
+                    x.inspect_err(|e| {
+                        if e.loop_ancestor().is_some() {
+                            warn!("Skipping symbolic link loop: {e}");
+                        }
+                    })
+                    .ok()
+                })
+                .filter(|e| !e.file_type().is_dir())
+                .filter_map(move |e| {
+                    let ext = self.media_extension(e.path())?;
+                    Some((e.path().to_owned(), ext))
+                })
+        })
+    }
 
-impl Config {
-    /// Try loading the configuration from a toml file
-    pub fn try_load(toml: &str) -> Result<Self> {
-        Figment::new()
-            .merge(Toml::file(toml))
-            .extract()
-            .map_err(Into::into)
+    /// Check if `path` found in a search path is a media file, recording its extension
+    ///
+    /// See [MediaWalk::find_search_files].
+    #[cfg(feature = "zip")]
+    fn is_search_file(&self, path: &Path) -> bool {
+        let Some(ext) = self.media_extension(path) else {
+            return false;
+        };
+        self.matched.borrow_mut().insert(ext);
+        true
+    }
+
+    /// Lowercase media extension of `path`, sniffed from the content of files without extension
+    ///
+    /// Returns `None` for files which are not media files.
+    fn media_extension(&self, path: &Path) -> Option<OsString> {
+        let ext = match path.extension() {
+            Some(ext) => ext.to_ascii_lowercase(),
+            None if self.match_extensionless => OsString::from(sniffed_extension(path)?),
+            None => return None,
+        };
+        self.extensions.contains(&ext).then_some(ext)
+    }
+
+    /// Warn about extensions which did not match any file, e.g. due to a typo
+    fn warn_unmatched_extensions(&self) {
+        let matched = self.matched.borrow();
+        let mut unmatched: Vec<_> = self.extensions.0.difference(&matched).collect();
+        unmatched.sort();
+        for ext in unmatched {
+            warn!("No files found with extension '{}'", ext.display());
+        }
+    }
+
+    /// Check if `path` or its file name matches an exclude pattern
+    fn is_excluded(&self, path: &Path) -> bool {
+        !self.exclude.is_empty()
+            && (self.exclude.is_match(path)
+                || path.file_name().is_some_and(|n| self.exclude.is_match(n)))
     }
 }
 
-/// Import media files according to the [Config]
-pub fn import_media_files(config: &Config) -> Result<()> {
-    let extensions: HashSet<OsString> = build_extension_set(&config.extensions)?;
+/// Map the canonical `paths` below `root` to paths as found when walking `root`
+fn paths_below(root: &Path, paths: &[PathBuf]) -> Vec<PathBuf> {
+    let Ok(canonical_root) = fs::canonicalize(root) else {
+        return Vec::new();
+    };
 
-    // Index existing media files
-    let existing = MediaFiles::from_paths(&config.existing_paths, &extensions);
+    paths
+        .iter()
+        .filter_map(|path| path.strip_prefix(&canonical_root).ok())
+        .map(|relative| root.join(relative))
+        .inspect(|path| debug!("Skipping {} while walking", path.display()))
+        .collect()
+}
 
-    // Synchronize files from search paths
-    sync_media_files(
-        &existing,
-        &config.search_paths,
-        &extensions,
-        Path::new(&config.output_path),
-    )
+/// Check if `path` is a zip archive by its extension
+fn is_zip_archive(path: &Path) -> bool {
+    path.is_file()
+        && path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("zip"))
 }
 
-/// Synchronize files to `output_path` which are not found in `existing`
-fn sync_media_files(
-    existing: &MediaFiles,
-    search_paths: &[String],
-    extensions: &HashSet<OsString>,
-    output_path: &Path,
-) -> Result<()> {
-    // Crawl through search paths
-    for path in find_media_files(search_paths, extensions) {
-        // Check for a match with an existing file
-        let key = hashed(path.file_name());
-        if let Some(existing) = existing.name_map.get(&key) {
-            // We have at least one file with the same filename.
-            // In the majority of cases, this is the exact same file.
-            // Reading the file size is cheap,
-            // reading the exif create date is more expensive via the slow connection.
-
-            // We check first if there is an exact size match and skip the duplicate in this case.
-            let file_size = file_size(&path)?;
-
-            if existing.iter().any(|e| e.size == file_size) {
-                debug!(
-                    "Identified {} as duplicate of an existing file (same name, both {file_size} bytes)",
-                    path.display(),
-                );
-                continue;
-            }
+/// Detect the file type of `path` from its magic bytes and return its usual extension
+fn sniffed_extension(path: &Path) -> Option<&'static str> {
+    infer::get_from_path(path)
+        .inspect_err(|e| debug!("Failed to sniff type of {}: {e}", path.display()))
+        .ok()
+        .flatten()
+        .map(|kind| kind.extension())
+}
 
-            // There is no size match, we have to check the exif date
-            // to identify if this is the same media file with differing quality.
-            let created = exif_created(&path).unwrap_or_default();
-            if let Some(existing) = existing.iter().find(|e| e.created == created) {
-                debug!(
-                    "File {} ({file_size} bytes) is already found at {} ({} bytes)",
-                    path.display(),
-                    existing.path.display(),
-                    existing.size,
-                );
+/// Map media files to the raw file with the same stem in the same directory
+fn raw_siblings(files: &[PathBuf], raw_extensions: &ExtensionSet) -> HashMap<PathBuf, PathBuf> {
+    let is_raw = |p: &PathBuf| raw_extensions.matches(p);
 
-                if file_size <= existing.size {
-                    // The new version is of lower or equal quality.
-                    debug!(
-                        "Skipping duplicate / lower-quality version of {}",
-                        path.display()
-                    );
-                    continue;
-                }
+    let raws: HashMap<PathBuf, &PathBuf> = files
+        .iter()
+        .filter(|p| is_raw(p))
+        .map(|p| (p.with_extension(""), p))
+        .collect();
+
+    files
+        .iter()
+        .filter(|p| !is_raw(p))
+        .filter_map(|p| {
+            raws.get(&p.with_extension(""))
+                .map(|raw| (p.clone(), (*raw).clone()))
+        })
+        .collect()
+}
+
+/// Map the videos of Live Photos in `files` to their image
+///
+/// A Live Photo is a HEIC image and a MOV video with the same stem in the same directory,
+/// e.g. `IMG_0001.HEIC` and `IMG_0001.MOV`.
+fn live_photo_siblings(files: &[PathBuf]) -> HashMap<PathBuf, PathBuf> {
+    let images = ExtensionSet(HashSet::from(["heic".into()]));
+    let videos = ExtensionSet(HashSet::from(["mov".into()]));
+
+    let images: HashMap<PathBuf, &PathBuf> = files
+        .iter()
+        .filter(|p| images.matches(p))
+        .map(|p| (p.with_extension(""), p))
+        .collect();
+
+    files
+        .iter()
+        .filter(|p| videos.matches(p))
+        .filter_map(|p| {
+            images
+                .get(&p.with_extension(""))
+                .map(|image| (p.clone(), (*image).clone()))
+        })
+        .collect()
+}
+
+/// Build the directory to place the media file at `path` created at `created` in
+///
+/// This is the date directory in the output path for the extension of the file,
+/// the `undated_dir_name` directory for files without creation date,
+/// or the output path itself with `flatten`.
+/// With `preserve_structure`, it is the directory relative to its search path instead.
+/// With `geohash_precision`, files with GPS coordinates are placed in a geohash subdirectory.
+fn target_directory(
+    path: &Path,
+    created: Option<&DateTime<FixedOffset>>,
+    config: &Config,
+) -> Result<PathBuf> {
+    let output_path = config.output_root(path);
+    if config.flatten {
+        return Ok(output_path.to_owned());
+    }
+
+    if config.preserve_structure {
+        // Relative to the innermost search path for nested search paths
+        let relative = config
+            .search_paths
+            .iter()
+            .filter_map(|root| path.strip_prefix(root).ok())
+            .min_by_key(|relative| relative.components().count())
+            .with_context(|| format!("{} is not in a search path", path.display()))?;
+        return Ok(output_path.join(relative.parent().unwrap_or(Path::new(""))));
+    }
+
+    let Some(created) = created else {
+        return Ok(output_path.join(config.undated_dir_name.as_deref().unwrap_or("_undated")));
+    };
+    let date_format = config
+        .date_format
+        .as_deref()
+        .or(config.bucket_depth.map(BucketDepth::date_format));
+    let mut dir = output_path.join(date_directory(
+        created,
+        date_format,
+        config.bucket_timezone,
+    )?);
+
+    if let Some(precision) = config.geohash_precision
+        && let Some((latitude, longitude)) = exif_gps(path)
+    {
+        dir.push(geohash(latitude, longitude, precision));
+    }
+
+    Ok(dir)
+}
+
+/// Encode `latitude` and `longitude` as geohash with `precision` characters
+fn geohash(latitude: f64, longitude: f64, precision: usize) -> String {
+    const BASE32: &[u8] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+
+    let mut lat = (-90.0, 90.0);
+    let mut lon = (-180.0, 180.0);
+    let mut hash = String::with_capacity(precision);
+    let mut even = true;
+
+    while hash.len() < precision {
+        let mut index = 0;
+        for _ in 0..5 {
+            let (range, value) = if even {
+                (&mut lon, longitude)
+            } else {
+                (&mut lat, latitude)
+            };
+            let mid = (range.0 + range.1) / 2.0;
+            index <<= 1;
+            if value >= mid {
+                index |= 1;
+                range.0 = mid;
+            } else {
+                range.1 = mid;
             }
+            even = !even;
         }
+        hash.push(BASE32[index] as char);
+    }
 
-        // Copy file to target location
-        let created = exif_created(&path).unwrap_or_default();
-        let date_path = output_path.join(format!(
+    hash
+}
+
+/// Build the relative date directory for `created`
+///
+/// Uses `date_format` if given and `YYYY_MM_DD` otherwise.
+/// The timestamp is converted to `timezone` first if given.
+fn date_directory(
+    created: &DateTime<FixedOffset>,
+    date_format: Option<&str>,
+    timezone: Option<Tz>,
+) -> Result<PathBuf> {
+    let created = match timezone {
+        Some(timezone) => created.with_timezone(&timezone).fixed_offset(),
+        None => *created,
+    };
+
+    match date_format {
+        Some(date_format) => {
+            let mut dir = String::new();
+            write!(dir, "{}", created.format(date_format))
+                .with_context(|| format!("failed to format date with '{date_format}'"))?;
+            Ok(PathBuf::from(dir))
+        }
+        None => Ok(PathBuf::from(format!(
             "{:04}_{:02}_{:02}",
             created.year(),
             created.month(),
             created.day()
-        ));
-        if !date_path.is_dir() {
-            debug!("Creating date directory {}", date_path.display());
-            fs::create_dir_all(&date_path)?;
+        ))),
+    }
+}
+
+/// File name of the media file at `path` created at `created` in the output path
+///
+/// Without `template`, the original file name is kept.
+fn target_file_name(
+    path: &Path,
+    created: &DateTime<FixedOffset>,
+    template: Option<&str>,
+    timezone: Option<Tz>,
+) -> Result<OsString> {
+    let orig = path.file_name().unwrap_or_default();
+    let Some(template) = template else {
+        return Ok(orig.to_owned());
+    };
+
+    let created = match timezone {
+        Some(timezone) => created.with_timezone(&timezone).fixed_offset(),
+        None => *created,
+    };
+
+    let mut name = OsString::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        name.push(&rest[..start]);
+        let Some(end) = rest[start..].find('}') else {
+            bail!("unclosed placeholder in file name template '{template}'");
+        };
+        match &rest[start + 1..start + end] {
+            "date" => name.push(created.format("%Y-%m-%d").to_string()),
+            "time" => name.push(created.format("%H%M%S").to_string()),
+            "orig" => name.push(orig),
+            placeholder => {
+                bail!("unknown placeholder '{{{placeholder}}}' in file name template '{template}'")
+            }
         }
+        rest = &rest[start + end + 1..];
+    }
+    name.push(rest);
 
-        let target_file = date_path.join(path.file_name().unwrap());
-        fs::copy(&path, &target_file)?;
-        debug!("Copied {} to {}", path.display(), target_file.display());
+    Ok(name)
+}
+
+/// Check that `template` is valid and produces a single file name
+fn check_filename_template(template: &str) -> Result<()> {
+    let sample = target_file_name(
+        Path::new("IMG_0001.jpg"),
+        &DateTime::default(),
+        Some(template),
+        None,
+    )?;
+    let mut components = Path::new(&sample).components();
+    if !matches!(
+        (components.next(), components.next()),
+        (Some(Component::Normal(_)), None)
+    ) {
+        bail!("file name template '{template}' must produce a file name without directories");
     }
 
     Ok(())
 }
 
-/// Set of existing [MediaFile]s
-struct MediaFiles {
-    /// Map from hashed filenames to vectors of indexed files by this name
-    name_map: HashMap<u64, Vec<MediaFile>>,
+/// Check that `date_format` is valid and produces a relative path without `.` or `..`
+fn check_date_format(date_format: &str) -> Result<()> {
+    if StrftimeItems::new(date_format).any(|item| matches!(item, Item::Error)) {
+        bail!("invalid date format '{date_format}'");
+    }
+
+    let sample = date_directory(&DateTime::default(), Some(date_format), None)?;
+    if sample.as_os_str().is_empty()
+        || sample
+            .components()
+            .any(|c| !matches!(c, Component::Normal(_)))
+    {
+        bail!("date format '{date_format}' must produce a relative path without '.' or '..'");
+    }
+
+    Ok(())
 }
 
-impl MediaFiles {
-    fn from_paths(paths: &[String], extensions: &HashSet<OsString>) -> Self {
-        let mut name_map = HashMap::new();
+/// Build set of glob patterns to exclude
+fn build_glob_set(patterns: &[String]) -> Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
 
-        for existing in paths.iter().flat_map(|p| {
-            WalkDir::new(p)
-                .into_iter()
-                .filter_map(|x| x.ok())
-                .filter(|e| !e.file_type().is_dir())
-                .filter_map(|e| match e.path().extension() {
-                    Some(ext) if extensions.contains(ext) => Some(e.path().to_owned()),
-                    _ => None,
-                })
-                .filter_map(|p| {
-                    MediaFile::try_from_path(&p)
-                        .inspect_err(|e| warn!("Failed to parse {}: {e:#}", p.display()))
-                        .ok()
-                })
-        }) {
-            let key = hashed(existing.path.file_name());
-            name_map
-                .entry(key)
-                .and_modify(|v: &mut Vec<MediaFile>| v.push(existing.clone()))
-                .or_insert_with(|| vec![existing]);
+    for pattern in patterns {
+        builder.add(
+            Glob::new(pattern).with_context(|| format!("invalid exclude pattern '{pattern}'"))?,
+        );
+    }
+
+    builder.build().map_err(Into::into)
+}
+
+/// Set of file extensions, matched like the extensions of the import configuration
+///
+/// Extensions are compared ignoring ASCII case and a leading `.` of a configured extension.
+///
+/// ```
+/// use std::path::Path;
+///
+/// use media_tools::ExtensionSet;
+///
+/// let extensions = ExtensionSet::new([".JPG", "heic"])?;
+/// assert!(extensions.matches(Path::new("DCIM/IMG_0001.jpg")));
+/// assert!(extensions.matches(Path::new("DCIM/IMG_0002.HEIC")));
+/// assert!(!extensions.matches(Path::new("DCIM/IMG_0001.jpg.xmp")));
+/// assert!(!extensions.matches(Path::new("DCIM/jpg")));
+/// # anyhow::Ok(())
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExtensionSet(HashSet<OsString>);
+
+impl ExtensionSet {
+    /// Build the set of `extensions`, e.g. `jpg` or `.jpg`
+    ///
+    /// Fails for empty extensions and extensions with an inner `.`:
+    ///
+    /// ```
+    /// use media_tools::ExtensionSet;
+    ///
+    /// assert!(ExtensionSet::new(["tar.gz"]).is_err());
+    /// assert!(ExtensionSet::new(["."]).is_err());
+    /// ```
+    pub fn new(
+        extensions: impl IntoIterator<Item = impl AsRef<str>>,
+    ) -> Result<Self, MediaToolsError> {
+        let mut exts = HashSet::new();
+
+        for extension in extensions {
+            let extension = extension.as_ref();
+            let trimmed = trim_dot(extension);
+            if trimmed.is_empty() || trimmed.contains('.') {
+                return Err(MediaToolsError::Config(anyhow!(
+                    "extensions must be non-empty without inner '.' but got '{extension}'"
+                )));
+            }
+            exts.insert(OsString::from(trimmed.to_ascii_lowercase()));
         }
 
-        Self { name_map }
+        Ok(Self(exts))
     }
-}
 
-/// Indexed media file
-#[derive(Debug, Clone)]
-struct MediaFile {
-    /// Full path to file
-    path: PathBuf,
-    /// Exif creation timestamp
-    created: DateTime<FixedOffset>,
-    /// File size in bytes
-    size: u64,
-}
+    /// Check if the extension of `path` is contained in the set, ignoring case
+    pub fn matches(&self, path: &Path) -> bool {
+        path.extension().is_some_and(|ext| self.contains(ext))
+    }
 
-impl MediaFile {
-    /// Try to read a file from the `path`
-    fn try_from_path(path: &Path) -> Result<Self> {
-        let created = exif_created(path).unwrap_or_default();
-        let size =
-            file_size(path).with_context(|| format!("failed to get size of {}", path.display()))?;
-        Ok(Self {
-            path: path.to_owned(),
-            created,
-            size,
-        })
+    /// Check if `extension` without leading `.` is contained in the set, ignoring case
+    ///
+    /// ```
+    /// use std::ffi::OsStr;
+    ///
+    /// use media_tools::ExtensionSet;
+    ///
+    /// let extensions = ExtensionSet::new(["mov"])?;
+    /// assert!(extensions.contains(OsStr::new("MOV")));
+    /// # anyhow::Ok(())
+    /// ```
+    pub fn contains(&self, extension: &OsStr) -> bool {
+        self.0.contains(&extension.to_ascii_lowercase())
     }
 }
 
-/// Find media files in `search_paths` matching `extensions`
-fn find_media_files<'a>(
-    search_paths: &'a [String],
-    extensions: &'a HashSet<OsString>,
-) -> impl Iterator<Item = PathBuf> + 'a {
-    search_paths.iter().flat_map(|s| {
-        WalkDir::new(s)
-            .into_iter()
-            .filter_map(|x| x.ok())
-            .filter(|e| !e.file_type().is_dir())
-            .filter_map(|e| match e.path().extension() {
-                Some(ext) if extensions.contains(ext) => Some(e.path().to_owned()),
-                _ => None,
-            })
-    })
+/// Strip a single leading `.` from a configured extension like `.jpg`
+fn trim_dot(extension: &str) -> &str {
+    extension.strip_prefix('.').unwrap_or(extension)
+}
+
+/// Exif tags consulted for the creation timestamp, in order of preference
+///
+/// `ModifyDate` comes last as raw converters store the time of the conversion in it,
+/// e.g. in the main IFD of a DNG, while the capture time is kept in the Exif IFD.
+const EXIF_DATE_TAGS: [ExifTag; 3] = [
+    ExifTag::CreateDate,
+    ExifTag::DateTimeOriginal,
+    ExifTag::ModifyDate,
+];
+
+/// Try to extract the exif creation timestamp from the file at `path`
+///
+/// Images (JPEG, HEIF/HEIC, TIFF-based raw formats like DNG) are read via their exif data,
+/// videos (MP4, MOV) via their track info.
+/// `nom_exif` detects the container from the file header, not the extension.
+///
+/// The tag configured in `date_tags` for the extension of `path` is consulted first.
+/// Afterwards, the first of [EXIF_DATE_TAGS] holding a timestamp is used for images
+/// and [TrackInfoTag::CreateDate] for videos.
+/// The latter covers the `mvhd` creation time in UTC
+/// as well as `com.apple.quicktime.creationdate` with its offset.
+/// Video timestamps in UTC are converted to `video_utc_offset` if configured.
+///
+/// With `exiftool_fallback`, files without a date are read with `exiftool` as well.
+///
+/// Returns `Ok(None)` for files without a date tag
+/// and an error for files which fail to be read or parsed.
+fn exif_created(path: &Path, config: &Config) -> Result<Option<DateTime<FixedOffset>>> {
+    exif_metadata(path, config).map(|metadata| metadata.created)
 }
 
-/// Build set of extension to crawl for
-fn build_extension_set(extensions: &[String]) -> Result<HashSet<OsString>> {
-    let mut exts = HashSet::new();
+/// Try to extract the exif creation timestamp and device from the file at `path`
+///
+/// See [exif_created] and [exif_device].
+fn exif_metadata(path: &Path, config: &Config) -> Result<Metadata> {
+    let file = File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    let metadata = exif_metadata_from(file, path.extension(), config)
+        .with_context(|| format!("failed to parse {}", path.display()));
 
-    for extension in extensions {
-        if extension.contains('.') {
-            bail!("extensions must not contain '.' but got '{extension}'");
+    #[cfg(feature = "exiftool")]
+    if config.exiftool_fallback
+        && !matches!(
+            metadata,
+            Ok(Metadata {
+                created: Some(_),
+                ..
+            })
+        )
+    {
+        info!("Falling back to exiftool for {}", path.display());
+        match crate::exiftool::create_date(path) {
+            Ok(Some(date)) => {
+                let device = metadata.ok().and_then(|m| m.device);
+                return Ok(Metadata {
+                    created: Some(date),
+                    device,
+                });
+            }
+            Ok(None) => debug!("No exiftool creation date in {}", path.display()),
+            Err(e) => warn!("Failed to read {} with exiftool: {e:#}", path.display()),
         }
-        exts.insert(OsString::from_str(extension)?);
     }
 
-    Ok(exts)
+    metadata
 }
 
-/// Try to extract the exif creation timestamp from the file at `path`
-fn exif_created(path: &Path) -> Option<DateTime<FixedOffset>> {
+/// Creation timestamp and device of a media file
+#[derive(Debug, Clone, Default)]
+struct Metadata {
+    /// Creation timestamp, `None` for undated files
+    created: Option<DateTime<FixedOffset>>,
+    /// Camera or device which created the file, see [exif_device]
+    device: Option<String>,
+}
+
+/// Try to extract the exif creation timestamp and device from `reader` of a file with `extension`
+///
+/// See [exif_created]. The time spent parsing is added to [EXIF_NANOS].
+fn exif_metadata_from<R: Read + Seek>(
+    reader: R,
+    extension: Option<&OsStr>,
+    config: &Config,
+) -> Result<Metadata> {
+    let start = Instant::now();
+    let metadata = parse_exif_metadata(reader, extension, config);
+    let nanos = u64::try_from(start.elapsed().as_nanos()).unwrap_or(u64::MAX);
+    EXIF_NANOS.fetch_add(nanos, Ordering::Relaxed);
+    metadata
+}
+
+/// Parse the exif creation timestamp and device from `reader`, see [exif_metadata_from]
+fn parse_exif_metadata<R: Read + Seek>(
+    reader: R,
+    extension: Option<&OsStr>,
+    config: &Config,
+) -> Result<Metadata> {
     fn extract_date(value: &EntryValue) -> Option<DateTime<FixedOffset>> {
         if let EntryValue::Time(create_date) = value {
             Some(*create_date)
@@ -234,32 +4308,399 @@ fn exif_created(path: &Path) -> Option<DateTime<FixedOffset>> {
         }
     }
 
+    let date_tag = extension.and_then(|ext| {
+        config
+            .date_tags
+            .iter()
+            .find(|(e, _)| ext.eq_ignore_ascii_case(trim_dot(e)))
+            .map(|(_, tag)| *tag)
+    });
+
+    let mut parser = MediaParser::new();
+    let src = MediaSource::seekable(reader)?;
+
+    if src.has_exif() {
+        let exif: ExifIter = parser.parse(src)?;
+        let exif: Exif = exif.into();
+        let created = date_tag
+            .and_then(|t| t.exif)
+            .into_iter()
+            .chain(EXIF_DATE_TAGS)
+            .find_map(|tag| {
+                let created = exif.get(tag).and_then(extract_date)?;
+                Some(if config.subsecond_precision {
+                    with_subseconds(created, &exif, tag)
+                } else {
+                    created
+                })
+            });
+        return Ok(Metadata {
+            created,
+            device: device_name(exif.get(ExifTag::Make), exif.get(ExifTag::Model)),
+        });
+    } else if src.has_track() {
+        let track_info: TrackInfo = parser.parse(src)?;
+        let created = date_tag
+            .and_then(|t| t.track)
+            .into_iter()
+            .chain([TrackInfoTag::CreateDate])
+            .find_map(|tag| track_info.get(tag).and_then(extract_date));
+        let created = match (created, config.video_offset()) {
+            (Some(created), Some(offset)) if created.offset().local_minus_utc() == 0 => {
+                Some(created.with_timezone(&offset))
+            }
+            (created, _) => created,
+        };
+        return Ok(Metadata {
+            created,
+            device: device_name(
+                track_info.get(TrackInfoTag::Make),
+                track_info.get(TrackInfoTag::Model),
+            ),
+        });
+    }
+
+    Ok(Metadata::default())
+}
+
+/// Add the fraction of a second of the sub-second tag belonging to the date `tag` to `created`
+///
+/// E.g. `SubSecTimeOriginal` of `25` adds 250 milliseconds to `DateTimeOriginal`.
+fn with_subseconds(
+    created: DateTime<FixedOffset>,
+    exif: &Exif,
+    tag: ExifTag,
+) -> DateTime<FixedOffset> {
+    let subsec_tag = match tag {
+        ExifTag::DateTimeOriginal => ExifTag::SubSecTimeOriginal,
+        ExifTag::CreateDate => ExifTag::SubSecTimeDigitized,
+        ExifTag::ModifyDate => ExifTag::SubSecTime,
+        _ => return created,
+    };
+    let Some(digits) = exif
+        .get(subsec_tag)
+        .and_then(EntryValue::as_str)
+        .map(str::trim)
+        .filter(|d| !d.is_empty() && d.bytes().all(|b| b.is_ascii_digit()))
+    else {
+        return created;
+    };
+
+    // The digits are the decimal places, so pad or cut them to nanoseconds
+    let nanos = format!("{digits:0<9}")[..9].parse().unwrap_or_default();
+    created.with_nanosecond(nanos).unwrap_or(created)
+}
+
+/// Check if the creation timestamps `a` and `b` are the same
+///
+/// Timestamps are compared by the second unless `subsecond_precision` is set.
+fn same_created(a: &DateTime<FixedOffset>, b: &DateTime<FixedOffset>, config: &Config) -> bool {
+    if config.subsecond_precision {
+        a == b
+    } else {
+        a.timestamp() == b.timestamp()
+    }
+}
+
+/// Take the exif creation timestamp of `result` for the file at `path`
+///
+/// Files without a date tag are common and only logged for debugging,
+/// unparseable exif data is logged as a warning.
+fn exif_created_logged(
+    result: Result<Option<DateTime<FixedOffset>>>,
+    path: &Path,
+) -> Option<DateTime<FixedOffset>> {
+    match result {
+        Ok(Some(created)) => Some(created),
+        Ok(None) => {
+            debug!("No exif creation date in {}", path.display());
+            None
+        }
+        Err(e) => {
+            warn!("Unparseable exif data in {}: {e:#}", path.display());
+            None
+        }
+    }
+}
+
+/// Try to extract the GPS coordinates from the file at `path`
+///
+/// Returns latitude and longitude in decimal degrees,
+/// negative for the southern and western hemispheres.
+pub fn exif_gps(path: &Path) -> Option<(f64, f64)> {
+    fn degrees(lat_lng: &LatLng, reference: char, negative: char) -> f64 {
+        let LatLng(d, m, s) = lat_lng;
+        let degrees = d.as_float() + m.as_float() / 60.0 + s.as_float() / 3600.0;
+        if reference.eq_ignore_ascii_case(&negative) {
+            -degrees
+        } else {
+            degrees
+        }
+    }
+
+    let mut parser = MediaParser::new();
+    let src = MediaSource::file_path(path).ok()?;
+
+    let gps = if src.has_exif() {
+        let exif: ExifIter = parser.parse(src).ok()?;
+        let exif: Exif = exif.into();
+        exif.get_gps_info().ok()??
+    } else if src.has_track() {
+        let track_info: TrackInfo = parser.parse(src).ok()?;
+        track_info.get_gps_info()?.clone()
+    } else {
+        return None;
+    };
+
+    Some((
+        degrees(&gps.latitude, gps.latitude_ref, 'S'),
+        degrees(&gps.longitude, gps.longitude_ref, 'W'),
+    ))
+}
+
+/// Try to extract the camera or device which created the file at `path`
+///
+/// Returns the `Make` and `Model` tags joined by a space, e.g. `Apple iPhone 13`,
+/// or only the model if it already starts with the make, e.g. `Canon EOS R6`.
+pub fn exif_device(path: &Path) -> Option<String> {
     let mut parser = MediaParser::new();
     let src = MediaSource::file_path(path).ok()?;
 
     if src.has_exif() {
         let exif: ExifIter = parser.parse(src).ok()?;
         let exif: Exif = exif.into();
-        return exif.get(ExifTag::CreateDate).and_then(extract_date);
+        device_name(exif.get(ExifTag::Make), exif.get(ExifTag::Model))
     } else if src.has_track() {
         let track_info: TrackInfo = parser.parse(src).ok()?;
-        return track_info
-            .get(TrackInfoTag::CreateDate)
-            .and_then(extract_date);
+        device_name(
+            track_info.get(TrackInfoTag::Make),
+            track_info.get(TrackInfoTag::Model),
+        )
+    } else {
+        None
+    }
+}
+
+/// Join the `make` and `model` tags to a device name, see [exif_device]
+fn device_name(make: Option<&EntryValue>, model: Option<&EntryValue>) -> Option<String> {
+    fn text(value: Option<&EntryValue>) -> Option<String> {
+        value
+            .and_then(EntryValue::as_str)
+            .map(|text| text.trim_matches(|c: char| c.is_whitespace() || c == '\0'))
+            .filter(|text| !text.is_empty())
+            .map(str::to_owned)
+    }
+
+    match (text(make), text(model)) {
+        (Some(make), Some(model))
+            if model
+                .get(..make.len())
+                .is_some_and(|prefix| prefix.eq_ignore_ascii_case(&make)) =>
+        {
+            Some(model)
+        }
+        (Some(make), Some(model)) => Some(format!("{make} {model}")),
+        (make, model) => make.or(model),
+    }
+}
+
+/// Media file to import
+#[derive(Clone, Copy)]
+enum Source<'a> {
+    /// File on disk
+    File(&'a Path),
+    /// File read into memory, e.g. from an archive
+    Memory(&'a MemoryFile),
+}
+
+/// Media file read into memory
+pub(crate) struct MemoryFile {
+    /// Path of the file, e.g. the archive path joined with the entry name
+    pub(crate) path: PathBuf,
+    /// Content of the file
+    pub(crate) data: Vec<u8>,
+    /// Modification time of the file
+    pub(crate) modified: Option<DateTime<FixedOffset>>,
+}
+
+impl Source<'_> {
+    /// Path of the media file
+    fn path(&self) -> &Path {
+        match self {
+            Self::File(path) => path,
+            Self::Memory(file) => &file.path,
+        }
+    }
+
+    /// Size of the media file in bytes
+    fn size(&self) -> Result<u64> {
+        match self {
+            Self::File(path) => file_size(path),
+            Self::Memory(file) => Ok(file.data.len() as u64),
+        }
+    }
+
+    /// Exif creation timestamp and device of the media file, see [exif_metadata]
+    fn exif_metadata(&self, config: &Config) -> Result<Metadata> {
+        match self {
+            Self::File(path) => exif_metadata(path, config),
+            Self::Memory(file) => {
+                exif_metadata_from(Cursor::new(&file.data), file.path.extension(), config)
+                    .with_context(|| format!("failed to parse {}", file.path.display()))
+            }
+        }
+    }
+
+    /// Modification time of the media file
+    fn modified(&self) -> Option<DateTime<FixedOffset>> {
+        match self {
+            Self::File(path) => modified_time(path),
+            Self::Memory(file) => file.modified,
+        }
+    }
+
+    /// Creation timestamp and device of the media file, see [creation_metadata]
+    fn creation_metadata(&self, config: &Config) -> Metadata {
+        match self {
+            Self::File(path) => creation_metadata(path, config),
+            Self::Memory(file) => {
+                let mut device = None;
+                let created = resolve_date(
+                    config,
+                    &file.path,
+                    || {
+                        let metadata = self.exif_metadata(config);
+                        let created = metadata.map(|m| {
+                            device = m.device;
+                            m.created
+                        });
+                        exif_created_logged(created, &file.path)
+                    },
+                    || self.modified(),
+                );
+                Metadata { created, device }
+            }
+        }
+    }
+
+    /// Hash of the content of the media file with `algorithm`
+    fn content_hash(&self, algorithm: HashAlgorithm) -> Result<Vec<u8>> {
+        match self {
+            Self::File(path) => content_hash(path, algorithm),
+            Self::Memory(file) => {
+                let mut hasher = algorithm.hasher();
+                io::Write::write_all(&mut hasher, &file.data)?;
+                Ok(hasher.finalize())
+            }
+        }
+    }
+
+    /// Content of the media file rotated according to its orientation, `None` if it is not rotated
+    #[cfg(feature = "auto-rotate")]
+    fn rotated(&self) -> Result<Option<Vec<u8>>> {
+        match self {
+            Self::File(path) => crate::rotate::rotated(&fs::read(path)?),
+            Self::Memory(file) => crate::rotate::rotated(&file.data),
+        }
     }
+}
+
+/// Determine the creation timestamp of the file at `path`
+///
+/// The exif creation timestamp and the filesystem modification time
+/// are combined according to the `date_strategy`.
+/// Returns `None` if no timestamp is available.
+fn creation_timestamp(path: &Path, config: &Config) -> Option<DateTime<FixedOffset>> {
+    creation_metadata(path, config).created
+}
+
+/// Determine the creation timestamp of the file at `path` like [creation_timestamp]
+///
+/// The device is taken from the exif data if it is read to date the file.
+fn creation_metadata(path: &Path, config: &Config) -> Metadata {
+    let mut device = None;
+    let created = resolve_date(
+        config,
+        path,
+        || {
+            let created = exif_metadata(path, config).map(|m| {
+                device = m.device;
+                m.created
+            });
+            exif_created_logged(created, path)
+        },
+        || modified_time(path),
+    );
+    Metadata { created, device }
+}
+
+/// Read the filesystem modification time of the file at `path`
+fn modified_time(path: &Path) -> Option<DateTime<FixedOffset>> {
+    let modified = fs::metadata(path).and_then(|m| m.modified()).ok()?;
+    Some(DateTime::<Local>::from(modified).fixed_offset())
+}
+
+/// Latest plausible creation timestamp, `max_future_skew_secs` from now
+fn latest_plausible(config: &Config) -> Option<DateTime<FixedOffset>> {
+    let skew = i64::try_from(config.max_future_skew_secs)
+        .ok()
+        .and_then(TimeDelta::try_seconds)
+        .unwrap_or(TimeDelta::MAX);
+    Local::now().fixed_offset().checked_add_signed(skew)
+}
+
+/// Combine the `exif` creation timestamp and `mtime` of the file at `path` by `date_strategy`
+///
+/// Each timestamp is only determined if needed.
+/// Timestamps further in the future than `max_future_skew_secs` are ignored.
+fn resolve_date(
+    config: &Config,
+    path: &Path,
+    exif: impl FnOnce() -> Option<DateTime<FixedOffset>>,
+    mtime: impl FnOnce() -> Option<DateTime<FixedOffset>>,
+) -> Option<DateTime<FixedOffset>> {
+    let latest = latest_plausible(config);
+    let plausible = |timestamp: Option<DateTime<FixedOffset>>, source: &str| {
+        timestamp.filter(|timestamp| {
+            let plausible = latest.is_none_or(|latest| *timestamp <= latest);
+            if !plausible {
+                warn!(
+                    "Ignoring {source} {timestamp} of {} in the future",
+                    path.display()
+                );
+            }
+            plausible
+        })
+    };
+    let exif = || plausible(exif(), "exif date");
+    let mtime = || plausible(mtime(), "modification time");
 
-    None
+    match config.date_strategy {
+        DateStrategy::ExifOnly => exif(),
+        DateStrategy::MtimeOnly => mtime(),
+        DateStrategy::ExifThenMtime => exif().or_else(|| {
+            debug!("Using modification time for {}", path.display());
+            mtime()
+        }),
+        DateStrategy::Earliest => match (exif(), mtime()) {
+            (Some(exif), Some(mtime)) if mtime < exif => {
+                debug!("Using earlier modification time for {}", path.display());
+                Some(mtime)
+            }
+            (exif, mtime) => exif.or(mtime),
+        },
+    }
 }
 
 /// Try to read the file size of the file at `path`
 fn file_size(path: &Path) -> Result<u64> {
-    #[cfg(target_os = "linux")]
+    #[cfg(unix)]
     {
         use std::os::unix::fs::MetadataExt;
         fs::metadata(path).map(|m| m.size()).map_err(Into::into)
     }
 
-    #[cfg(target_os = "windows")]
+    #[cfg(windows)]
     {
         use std::os::windows::fs::MetadataExt;
         fs::metadata(path)
@@ -268,9 +4709,81 @@ fn file_size(path: &Path) -> Result<u64> {
     }
 }
 
-/// Get the hashed value of `data`
-fn hashed<H: Hash>(data: H) -> u64 {
-    let mut hasher = DefaultHasher::new();
-    data.hash(&mut hasher);
-    hasher.finish()
+/// Compute the hash of the content of the file at `path` with `algorithm`
+fn content_hash(path: &Path, algorithm: HashAlgorithm) -> Result<Vec<u8>> {
+    let mut file =
+        File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    let mut hasher = algorithm.hasher();
+    io::copy(&mut file, &mut hasher)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    Ok(hasher.finalize())
+}
+
+/// Algorithm to hash file contents with
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    /// SHA-256, checkable with `sha256sum`
+    #[default]
+    Sha256,
+    /// BLAKE3, much faster on large files, checkable with `b3sum`
+    Blake3,
+    /// 64-bit XXH3, fastest but not collision-resistant
+    Xxh3,
+}
+
+impl HashAlgorithm {
+    /// Create a hasher for this algorithm
+    fn hasher(self) -> Hasher {
+        match self {
+            Self::Sha256 => Hasher::Sha256(Sha256::new()),
+            Self::Blake3 => Hasher::Blake3(Box::new(blake3::Hasher::new())),
+            Self::Xxh3 => Hasher::Xxh3(Box::new(Xxh3::new())),
+        }
+    }
+}
+
+impl fmt::Display for HashAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Sha256 => "sha256",
+            Self::Blake3 => "blake3",
+            Self::Xxh3 => "xxh3",
+        })
+    }
+}
+
+/// Incremental hasher of a [HashAlgorithm], fed by writing to it
+enum Hasher {
+    Sha256(Sha256),
+    Blake3(Box<blake3::Hasher>),
+    Xxh3(Box<Xxh3>),
+}
+
+impl Hasher {
+    /// Finish hashing and return the hash as bytes
+    fn finalize(self) -> Vec<u8> {
+        match self {
+            Self::Sha256(hasher) => hasher.finalize().to_vec(),
+            Self::Blake3(hasher) => hasher.finalize().as_bytes().to_vec(),
+            Self::Xxh3(hasher) => hasher.digest().to_be_bytes().to_vec(),
+        }
+    }
+}
+
+impl io::Write for Hasher {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Sha256(hasher) => hasher.update(buf),
+            Self::Blake3(hasher) => {
+                hasher.update(buf);
+            }
+            Self::Xxh3(hasher) => hasher.update(buf),
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
 }
@@ -1,5 +1,25 @@
 //! Media tools
 
+#[cfg(feature = "zip")]
+mod archive;
+#[cfg(feature = "backfill-exif")]
+mod backfill;
+mod error;
+#[cfg(feature = "exiftool")]
+mod exiftool;
 mod import;
+#[cfg(feature = "perceptual-hash")]
+mod phash;
+#[cfg(feature = "auto-rotate")]
+mod rotate;
+mod throttle;
 
-pub use self::import::{Config as ImportConfig, import_media_files};
+pub use self::error::MediaToolsError;
+pub use self::import::{
+    AmbiguousMatch, BucketDepth, CollisionStrategy, Config as ImportConfig, DateStrategy, DateTag,
+    Duplicate, ExtensionSet, HashAlgorithm, ImportAction, ImportDecision, ImportObserver,
+    ImportPlan, ImportRecord, LinkMode, MediaCount, MediaFile, OutputRoute, QualityHeuristic,
+    SkipReason, Statistics as ImportStatistics, Timings as ImportTimings, count_media_files,
+    exif_device, exif_gps, find_media_files, import_media_files, import_media_files_reporting,
+    import_media_files_with_observer, index_media_files, plan_import, resolve_target,
+};
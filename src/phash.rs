@@ -0,0 +1,32 @@
+//! Perceptual hashing
+//!
+//! This module computes difference hashes (dHash) of images.
+//! Visually similar images, e.g. re-exported JPEGs, have hashes with a small Hamming distance.
+
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use image::{ImageReader, imageops::FilterType};
+
+/// Compute the 64-bit difference hash of the image at `path`
+///
+/// The image is scaled to 9x8 grayscale pixels
+/// and each bit encodes whether a pixel is brighter than its right neighbor.
+pub(crate) fn dhash(path: &Path) -> Result<u64> {
+    let image = ImageReader::open(path)
+        .with_context(|| format!("failed to open {}", path.display()))?
+        .with_guessed_format()?
+        .decode()
+        .with_context(|| format!("failed to decode {}", path.display()))?;
+    let pixels = image.resize_exact(9, 8, FilterType::Triangle).to_luma8();
+
+    let mut hash = 0;
+    for y in 0..8 {
+        for x in 0..8 {
+            let bit = pixels.get_pixel(x, y)[0] > pixels.get_pixel(x + 1, y)[0];
+            hash = (hash << 1) | u64::from(bit);
+        }
+    }
+
+    Ok(hash)
+}
@@ -0,0 +1,1019 @@
+//! JPEG rotation
+//!
+//! This module rotates JPEG files according to their exif orientation without loss,
+//! like `jpegtran` does: the quantized DCT coefficients are rearranged and encoded
+//! again with optimized Huffman tables, but the image itself is never decoded.
+//!
+//! Only baseline JPEG files in a single scan are rotated, and only if their dimensions
+//! allow a perfect transformation, i.e. the edges moved to the top or left end at a
+//! full MCU. Other files are left unchanged with their orientation.
+
+use anyhow::{Context, Result, bail, ensure};
+use image::metadata::Orientation;
+use tracing::info;
+
+/// Prefix of the APP1 segment holding the exif data
+const EXIF_PREFIX: &[u8] = b"Exif\0\0";
+
+/// Markers of the segments handled by the transformation
+const SOF0: u8 = 0xc0;
+const SOF1: u8 = 0xc1;
+const DHT: u8 = 0xc4;
+const SOI: u8 = 0xd8;
+const EOI: u8 = 0xd9;
+const SOS: u8 = 0xda;
+const DQT: u8 = 0xdb;
+const DRI: u8 = 0xdd;
+const APP1: u8 = 0xe1;
+
+/// Natural (row-major) index of each coefficient of a block in zigzag order
+const ZIGZAG: [usize; 64] = [
+    0, 1, 8, 16, 9, 2, 3, 10, 17, 24, 32, 25, 18, 11, 4, 5, 12, 19, 26, 33, 40, 48, 41, 34, 27, 20,
+    13, 6, 7, 14, 21, 28, 35, 42, 49, 56, 57, 50, 43, 36, 29, 22, 15, 23, 30, 37, 44, 51, 58, 59,
+    52, 45, 38, 31, 39, 46, 53, 60, 61, 54, 47, 55, 62, 63,
+];
+
+/// Rotate the JPEG file `data` according to its exif orientation
+///
+/// Returns `None` if the file has no orientation to apply or cannot be rotated without loss,
+/// in which case it is left unchanged.
+pub(crate) fn rotated(data: &[u8]) -> Result<Option<Vec<u8>>> {
+    let mut jpeg = Jpeg::parse(data)?;
+
+    let Some(exif) = jpeg
+        .segments
+        .iter_mut()
+        .find(|s| s.marker == APP1 && s.data.starts_with(EXIF_PREFIX))
+    else {
+        return Ok(None);
+    };
+    // This resets the orientation in place
+    let mut tiff = exif.data[EXIF_PREFIX.len()..].to_vec();
+    let transform = match Orientation::remove_from_exif_chunk(&mut tiff) {
+        Some(Orientation::NoTransforms) | None => return Ok(None),
+        Some(orientation) => Transform::from(orientation),
+    };
+    let Some(scan) = jpeg.scan.take() else {
+        info!("Not rotating progressive or multi-scan JPEG file");
+        return Ok(None);
+    };
+    let frame = jpeg.frame.transformed(&transform);
+    if !frame.is_perfect(&transform) {
+        info!(
+            "Not rotating JPEG file of {}x{} pixels, which cannot be rotated without loss",
+            jpeg.frame.width, jpeg.frame.height
+        );
+        return Ok(None);
+    }
+    exif.data.truncate(EXIF_PREFIX.len());
+    exif.data.extend(tiff);
+
+    for segment in &mut jpeg.segments {
+        match segment.marker {
+            SOF0 | SOF1 => segment.data = frame.encode(),
+            DQT if transform.transpose => segment.data = transposed_quantization(&segment.data)?,
+            _ => {}
+        }
+    }
+    let blocks = transform.apply(&scan.blocks, &frame);
+
+    Ok(Some(encode(&jpeg.segments, &frame, &scan.tables, &blocks)))
+}
+
+/// Lossless transformation of an image, mirroring in the coordinates of the result
+#[derive(Debug, Clone, Copy)]
+struct Transform {
+    /// Swap rows and columns
+    transpose: bool,
+    /// Mirror the columns
+    flip_x: bool,
+    /// Mirror the rows
+    flip_y: bool,
+}
+
+impl From<Orientation> for Transform {
+    /// Transformation that displays an image with `orientation` upright
+    fn from(orientation: Orientation) -> Self {
+        let (transpose, flip_x, flip_y) = match orientation {
+            Orientation::NoTransforms => (false, false, false),
+            Orientation::FlipHorizontal => (false, true, false),
+            Orientation::Rotate180 => (false, true, true),
+            Orientation::FlipVertical => (false, false, true),
+            Orientation::Rotate90FlipH => (true, false, false),
+            Orientation::Rotate90 => (true, true, false),
+            Orientation::Rotate270FlipH => (true, true, true),
+            Orientation::Rotate270 => (true, false, true),
+        };
+        Self {
+            transpose,
+            flip_x,
+            flip_y,
+        }
+    }
+}
+
+impl Transform {
+    /// Transform the `blocks` of each component into the grids of the transformed `frame`
+    fn apply(&self, blocks: &[Blocks], frame: &Frame) -> Vec<Blocks> {
+        blocks
+            .iter()
+            .zip(&frame.components)
+            .map(|(blocks, component)| {
+                let mut transformed = Blocks::new(frame.grid(component));
+                for y in 0..transformed.height {
+                    for x in 0..transformed.width {
+                        let a = if self.flip_x {
+                            transformed.width - 1 - x
+                        } else {
+                            x
+                        };
+                        let b = if self.flip_y {
+                            transformed.height - 1 - y
+                        } else {
+                            y
+                        };
+                        let source = if self.transpose {
+                            blocks.get(b, a)
+                        } else {
+                            blocks.get(a, b)
+                        };
+                        *transformed.get_mut(x, y) = self.block(source);
+                    }
+                }
+                transformed
+            })
+            .collect()
+    }
+
+    /// Transform the coefficients of a block in natural order
+    fn block(&self, block: &[i16; 64]) -> [i16; 64] {
+        let mut transformed = [0; 64];
+        for v in 0..8 {
+            for u in 0..8 {
+                let value = if self.transpose {
+                    block[u * 8 + v]
+                } else {
+                    block[v * 8 + u]
+                };
+                // Mirroring negates the coefficients of odd frequencies
+                let negate = (self.flip_x && u % 2 == 1) != (self.flip_y && v % 2 == 1);
+                transformed[v * 8 + u] = if negate { -value } else { value };
+            }
+        }
+        transformed
+    }
+}
+
+/// Segment of a JPEG file, without its length
+struct Segment {
+    marker: u8,
+    data: Vec<u8>,
+}
+
+/// Component of a frame
+#[derive(Clone, Copy)]
+struct Component {
+    id: u8,
+    /// Horizontal sampling factor
+    h: usize,
+    /// Vertical sampling factor
+    v: usize,
+    /// Number of the quantization table
+    quantization: u8,
+}
+
+/// Frame header of a JPEG file
+struct Frame {
+    width: usize,
+    height: usize,
+    components: Vec<Component>,
+}
+
+impl Frame {
+    /// Parse the data of a SOF segment
+    fn parse(data: &[u8]) -> Result<Self> {
+        ensure!(data.len() >= 6, "truncated frame header");
+        ensure!(data[0] == 8, "unsupported sample precision {}", data[0]);
+        let height = usize::from(u16::from_be_bytes([data[1], data[2]]));
+        let width = usize::from(u16::from_be_bytes([data[3], data[4]]));
+        let count = data[5];
+        ensure!(width > 0 && height > 0, "unsupported size {width}x{height}");
+        let Some(components) = data.get(6..6 + 3 * usize::from(count)) else {
+            bail!("truncated frame header");
+        };
+
+        let components = components
+            .chunks_exact(3)
+            .map(|c| {
+                let (h, v) = (usize::from(c[1] >> 4), usize::from(c[1] & 0x0f));
+                ensure!(
+                    (1..=4).contains(&h) && (1..=4).contains(&v),
+                    "invalid sampling factors {:#x}",
+                    c[1]
+                );
+                Ok(Component {
+                    id: c[0],
+                    h,
+                    v,
+                    quantization: c[2],
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        ensure!(!components.is_empty(), "frame without components");
+
+        Ok(Self {
+            width,
+            height,
+            components,
+        })
+    }
+
+    /// Encode the data of a SOF segment
+    fn encode(&self) -> Vec<u8> {
+        let mut data = vec![8];
+        data.extend((self.height as u16).to_be_bytes());
+        data.extend((self.width as u16).to_be_bytes());
+        data.push(self.components.len() as u8);
+        for c in &self.components {
+            data.extend([c.id, (c.h << 4 | c.v) as u8, c.quantization]);
+        }
+        data
+    }
+
+    /// Frame of the image after applying `transform`
+    fn transformed(&self, transform: &Transform) -> Self {
+        let swap = |a, b| if transform.transpose { (b, a) } else { (a, b) };
+        let (width, height) = swap(self.width, self.height);
+        let components = self
+            .components
+            .iter()
+            .map(|c| {
+                let (h, v) = swap(c.h, c.v);
+                Component { h, v, ..*c }
+            })
+            .collect();
+        Self {
+            width,
+            height,
+            components,
+        }
+    }
+
+    /// Check if the edges `transform` moved to the top or left of this frame end at a full MCU
+    ///
+    /// Otherwise the padding of the partial MCUs would become visible.
+    fn is_perfect(&self, transform: &Transform) -> bool {
+        let (h_max, v_max) = self.max_sampling();
+        (!transform.flip_x || self.width.is_multiple_of(8 * h_max))
+            && (!transform.flip_y || self.height.is_multiple_of(8 * v_max))
+    }
+
+    /// Maximum horizontal and vertical sampling factors of the components
+    fn max_sampling(&self) -> (usize, usize) {
+        let h = self.components.iter().map(|c| c.h).max().unwrap_or(1);
+        let v = self.components.iter().map(|c| c.v).max().unwrap_or(1);
+        (h, v)
+    }
+
+    /// Size in blocks of the `component`, padded to full MCUs
+    fn grid(&self, component: &Component) -> (usize, usize) {
+        let (h_max, v_max) = self.max_sampling();
+        (
+            self.width.div_ceil(8 * h_max) * component.h,
+            self.height.div_ceil(8 * v_max) * component.v,
+        )
+    }
+
+    /// Number of MCU columns and rows of a scan of the components at `indices`
+    ///
+    /// A scan of a single component has one block per MCU and is not padded.
+    fn mcu_grid(&self, indices: &[usize]) -> (usize, usize) {
+        let (h_max, v_max) = self.max_sampling();
+        match *indices {
+            [index] => {
+                let c = &self.components[index];
+                (
+                    (self.width * c.h).div_ceil(h_max).div_ceil(8),
+                    (self.height * c.v).div_ceil(v_max).div_ceil(8),
+                )
+            }
+            _ => (
+                self.width.div_ceil(8 * h_max),
+                self.height.div_ceil(8 * v_max),
+            ),
+        }
+    }
+
+    /// Number of blocks coded in a scan of the components at `indices`
+    fn coded_blocks(&self, indices: &[usize]) -> usize {
+        let (columns, rows) = self.mcu_grid(indices);
+        let per_mcu = match *indices {
+            [_] => 1,
+            _ => indices
+                .iter()
+                .map(|&index| self.components[index].h * self.components[index].v)
+                .sum(),
+        };
+        columns * rows * per_mcu
+    }
+
+    /// Blocks of each MCU of a scan of the components at `indices`
+    ///
+    /// The blocks are given as component index and position in the [Frame::grid],
+    /// see [Frame::mcu_grid].
+    fn mcus<'a>(
+        &'a self,
+        indices: &'a [usize],
+    ) -> impl Iterator<Item = Vec<(usize, usize, usize)>> + 'a {
+        let (columns, rows) = self.mcu_grid(indices);
+        (0..rows).flat_map(move |y| {
+            (0..columns).map(move |x| match *indices {
+                [index] => vec![(index, x, y)],
+                _ => indices
+                    .iter()
+                    .flat_map(|&index| {
+                        let c = &self.components[index];
+                        (0..c.v).flat_map(move |v| {
+                            (0..c.h).map(move |h| (index, x * c.h + h, y * c.v + v))
+                        })
+                    })
+                    .collect(),
+            })
+        })
+    }
+}
+
+/// Quantized DCT coefficients of the blocks of a component in natural order
+struct Blocks {
+    /// Width in blocks
+    width: usize,
+    /// Height in blocks
+    height: usize,
+    coefficients: Vec<[i16; 64]>,
+}
+
+impl Blocks {
+    /// Create a grid of blocks of zeros
+    fn new((width, height): (usize, usize)) -> Self {
+        Self {
+            width,
+            height,
+            coefficients: vec![[0; 64]; width * height],
+        }
+    }
+
+    fn get(&self, x: usize, y: usize) -> &[i16; 64] {
+        &self.coefficients[y * self.width + x]
+    }
+
+    fn get_mut(&mut self, x: usize, y: usize) -> &mut [i16; 64] {
+        &mut self.coefficients[y * self.width + x]
+    }
+}
+
+/// Decoded scan of all components of a baseline JPEG file
+struct Scan {
+    /// Numbers of the DC and AC Huffman tables of each component of the frame
+    tables: Vec<(usize, usize)>,
+    /// Blocks of each component of the frame
+    blocks: Vec<Blocks>,
+}
+
+impl Scan {
+    /// Decode the scan with the `header` from the entropy-coded `data` following it
+    ///
+    /// Returns the scan, or `None` if it does not contain all components of the frame,
+    /// and the length of the entropy-coded data.
+    fn decode(
+        frame: &Frame,
+        header: &[u8],
+        data: &[u8],
+        tables: &[[Option<HuffmanTable>; 4]; 2],
+        restart_interval: usize,
+    ) -> Result<(Option<Self>, usize)> {
+        let count = usize::from(*header.first().context("truncated scan header")?);
+        let Some(selectors) = header.get(1..1 + 2 * count) else {
+            bail!("truncated scan header");
+        };
+        let mut scan_tables = vec![(0, 0); frame.components.len()];
+        let mut indices = Vec::with_capacity(count);
+        for selector in selectors.chunks_exact(2) {
+            let index = frame
+                .components
+                .iter()
+                .position(|c| c.id == selector[0])
+                .context("scan of unknown component")?;
+            ensure!(!indices.contains(&index), "component scanned twice");
+            scan_tables[index] = (
+                usize::from(selector[1] >> 4),
+                usize::from(selector[1] & 0x0f),
+            );
+            indices.push(index);
+        }
+        if indices.len() != frame.components.len() {
+            return Ok((None, entropy_data_length(data)));
+        }
+
+        let table = |class: usize, id: usize| {
+            tables[class]
+                .get(id)
+                .and_then(Option::as_ref)
+                .map(HuffmanTable::decoder)
+                .context("missing Huffman table")
+        };
+        let decoders = scan_tables
+            .iter()
+            .map(|&(dc, ac)| Ok((table(0, dc)?, table(1, ac)?)))
+            .collect::<Result<Vec<_>>>()?;
+
+        // Each block is coded with at least two bits, so that a frame header claiming
+        // far more pixels than the data holds is rejected before allocating its blocks
+        ensure!(
+            frame.coded_blocks(&indices) <= 4 * data.len(),
+            "frame of {}x{} pixels exceeds the scan data",
+            frame.width,
+            frame.height
+        );
+        let mut blocks: Vec<_> = frame
+            .components
+            .iter()
+            .map(|c| Blocks::new(frame.grid(c)))
+            .collect();
+        let mut predictions = vec![0; frame.components.len()];
+        let mut reader = BitReader::new(data);
+        for (unit, mcu) in frame.mcus(&indices).enumerate() {
+            if restart_interval > 0 && unit > 0 && unit % restart_interval == 0 {
+                reader.restart()?;
+                predictions.fill(0);
+            }
+            for (index, x, y) in mcu {
+                let (dc, ac) = &decoders[index];
+                let block = blocks[index].get_mut(x, y);
+                decode_block(&mut reader, dc, ac, &mut predictions[index], block)?;
+            }
+        }
+
+        let length = reader.pos + entropy_data_length(&data[reader.pos..]);
+        let scan = Self {
+            tables: scan_tables,
+            blocks,
+        };
+        Ok((Some(scan), length))
+    }
+}
+
+/// Parsed JPEG file
+struct Jpeg {
+    /// Segments before the scan except for Huffman tables and restart intervals
+    segments: Vec<Segment>,
+    frame: Frame,
+    /// Decoded scan, `None` if the file is not supported
+    scan: Option<Scan>,
+}
+
+impl Jpeg {
+    /// Parse the JPEG file `data` and decode its scan if it is supported
+    fn parse(data: &[u8]) -> Result<Self> {
+        ensure!(data.starts_with(&[0xff, SOI]), "not a JPEG file");
+        let mut pos = 2;
+        let mut segments = Vec::new();
+        let mut frame = None;
+        let mut baseline = false;
+        let mut tables: [[Option<HuffmanTable>; 4]; 2] = Default::default();
+        let mut restart_interval = 0;
+
+        loop {
+            let marker = next_marker(data, &mut pos)?;
+            ensure!(marker != EOI, "missing scan");
+            let Some(&[high, low]) = data.get(pos..pos + 2) else {
+                bail!("truncated segment");
+            };
+            let length = usize::from(u16::from_be_bytes([high, low])).max(2);
+            let Some(segment) = data.get(pos + 2..pos + length) else {
+                bail!("truncated segment");
+            };
+            pos += length;
+
+            match marker {
+                SOF0 | SOF1 => {
+                    frame = Some(Frame::parse(segment)?);
+                    baseline = true;
+                }
+                // Progressive, lossless, hierarchical or arithmetic coding
+                0xc2 | 0xc3 | 0xc5..=0xc7 | 0xc9..=0xcb | 0xcd..=0xcf => {
+                    frame = Some(Frame::parse(segment)?);
+                }
+                DHT => {
+                    let mut rest = segment;
+                    while let [class_id, ref table @ ..] = *rest {
+                        let (class, id) = (usize::from(class_id >> 4), usize::from(class_id & 0xf));
+                        ensure!(class < 2 && id < 4, "invalid Huffman table {class_id:#x}");
+                        let (table, length) = HuffmanTable::parse(table)?;
+                        tables[class][id] = Some(table);
+                        rest = &rest[1 + length..];
+                    }
+                    continue;
+                }
+                DRI => {
+                    let Some(&[high, low]) = segment.get(..2) else {
+                        bail!("truncated restart interval");
+                    };
+                    restart_interval = usize::from(u16::from_be_bytes([high, low]));
+                    continue;
+                }
+                SOS => {
+                    let frame = frame.context("scan before frame header")?;
+                    let mut scan = None;
+                    if baseline {
+                        let (decoded, length) =
+                            Scan::decode(&frame, segment, &data[pos..], &tables, restart_interval)?;
+                        pos += length;
+                        // Files with further scans are not supported
+                        if next_marker(data, &mut pos)? == EOI {
+                            scan = decoded;
+                        }
+                    }
+                    return Ok(Self {
+                        segments,
+                        frame,
+                        scan,
+                    });
+                }
+                _ => {}
+            }
+            segments.push(Segment {
+                marker,
+                data: segment.to_vec(),
+            });
+        }
+    }
+}
+
+/// Read the marker at `pos` after optional fill bytes and advance past it
+fn next_marker(data: &[u8], pos: &mut usize) -> Result<u8> {
+    while data.get(*pos..*pos + 2) == Some(&[0xff, 0xff]) {
+        *pos += 1;
+    }
+    let Some(&[0xff, marker]) = data.get(*pos..*pos + 2) else {
+        bail!("missing marker at offset {pos}");
+    };
+    *pos += 2;
+    Ok(marker)
+}
+
+/// Length of the entropy-coded `data` up to the next marker other than a restart marker
+fn entropy_data_length(data: &[u8]) -> usize {
+    data.windows(2)
+        .position(|w| w[0] == 0xff && !matches!(w[1], 0x00 | 0xd0..=0xd7 | 0xff))
+        .unwrap_or(data.len())
+}
+
+/// Transpose the quantization tables of the data of a DQT segment
+fn transposed_quantization(data: &[u8]) -> Result<Vec<u8>> {
+    let mut transposed = Vec::with_capacity(data.len());
+    let mut rest = data;
+    while let [precision_id, ref values @ ..] = *rest {
+        let size = if precision_id >> 4 == 0 { 1 } else { 2 };
+        let Some(values) = values.get(..64 * size) else {
+            bail!("truncated quantization table");
+        };
+        transposed.push(precision_id);
+        for n in ZIGZAG {
+            // Position of the transposed coefficient in zigzag order
+            let k = ZIGZAG
+                .iter()
+                .position(|&m| m == (n % 8) * 8 + n / 8)
+                .unwrap_or_default();
+            transposed.extend(&values[k * size..(k + 1) * size]);
+        }
+        rest = &rest[1 + 64 * size..];
+    }
+    Ok(transposed)
+}
+
+/// Decode a block in zigzag order into natural order
+///
+/// The DC coefficient is coded as difference to the `prediction` of the component.
+fn decode_block(
+    reader: &mut BitReader,
+    dc: &HuffmanDecoder,
+    ac: &HuffmanDecoder,
+    prediction: &mut i32,
+    block: &mut [i16; 64],
+) -> Result<()> {
+    // Differences of 8-bit samples have at most 11 bits, and coefficients at most 10
+    let size = dc.decode(reader)?;
+    ensure!(size <= 11, "invalid DC coefficient size {size}");
+    *prediction += reader.receive(size)?;
+    ensure!(
+        prediction.abs() <= i32::from(i16::MAX),
+        "DC coefficient out of range"
+    );
+    block[0] = *prediction as i16;
+
+    let mut k = 1;
+    while k < 64 {
+        let symbol = ac.decode(reader)?;
+        let (run, size) = (usize::from(symbol >> 4), symbol & 0x0f);
+        if size == 0 {
+            if run < 15 {
+                // End of block
+                break;
+            }
+            k += 16;
+            continue;
+        }
+        ensure!(size <= 10, "invalid AC coefficient size {size}");
+        k += run;
+        ensure!(k < 64, "invalid coefficient run");
+        block[ZIGZAG[k]] = reader.receive(size)? as i16;
+        k += 1;
+    }
+    Ok(())
+}
+
+/// Encode the scan with the transformed `blocks` after the `segments`
+///
+/// The Huffman tables are optimized for the blocks, which keeps the file size close
+/// to the original. Restart markers are dropped.
+fn encode(
+    segments: &[Segment],
+    frame: &Frame,
+    tables: &[(usize, usize)],
+    blocks: &[Blocks],
+) -> Vec<u8> {
+    let mut frequencies = [[[0; 256]; 4]; 2];
+    encode_blocks(frame, tables, blocks, |class, id, symbol, _, _| {
+        frequencies[class][id][usize::from(symbol)] += 1;
+    });
+    let huffman = frequencies.map(|frequencies| {
+        frequencies.map(|frequencies| {
+            frequencies
+                .iter()
+                .any(|&f| f > 0)
+                .then(|| HuffmanTable::optimal(&frequencies))
+        })
+    });
+
+    let mut out = vec![0xff, SOI];
+    let mut write_segment = |marker: u8, data: &[u8]| {
+        out.extend([0xff, marker]);
+        out.extend((data.len() as u16 + 2).to_be_bytes());
+        out.extend(data);
+    };
+    for segment in segments {
+        write_segment(segment.marker, &segment.data);
+    }
+
+    let mut data = Vec::new();
+    for (class, huffman) in huffman.iter().enumerate() {
+        for (id, table) in huffman.iter().enumerate() {
+            if let Some(table) = table {
+                data.push((class << 4 | id) as u8);
+                data.extend(table.bits);
+                data.extend(&table.values);
+            }
+        }
+    }
+    write_segment(DHT, &data);
+
+    let mut data = vec![frame.components.len() as u8];
+    for (c, (dc, ac)) in frame.components.iter().zip(tables) {
+        data.extend([c.id, (dc << 4 | ac) as u8]);
+    }
+    // Spectral selection and successive approximation of baseline scans
+    data.extend([0, 63, 0]);
+    write_segment(SOS, &data);
+
+    let codes = huffman.map(|huffman| huffman.map(|table| table.map(|t| t.codes())));
+    let mut writer = BitWriter::new(out);
+    encode_blocks(frame, tables, blocks, |class, id, symbol, bits, size| {
+        if let Some(codes) = &codes[class][id] {
+            let (code, length) = codes[usize::from(symbol)];
+            writer.write(code, length);
+        }
+        writer.write(bits, size);
+    });
+    let mut out = writer.finish();
+    out.extend([0xff, EOI]);
+    out
+}
+
+/// Pass the symbols coding the `blocks` of all components of `frame` in one scan to `emit`
+///
+/// `emit` is called with the table class (0 for DC, 1 for AC), table number, symbol
+/// and the additional bits with their size.
+fn encode_blocks(
+    frame: &Frame,
+    tables: &[(usize, usize)],
+    blocks: &[Blocks],
+    mut emit: impl FnMut(usize, usize, u8, u16, u8),
+) {
+    let indices: Vec<_> = (0..frame.components.len()).collect();
+    let mut predictions = vec![0; frame.components.len()];
+    for mcu in frame.mcus(&indices) {
+        for (index, x, y) in mcu {
+            let block = blocks[index].get(x, y);
+            let (dc, ac) = tables[index];
+
+            let (size, bits) = magnitude(i32::from(block[0]) - predictions[index]);
+            predictions[index] = i32::from(block[0]);
+            emit(0, dc, size, bits, size);
+
+            let mut run = 0;
+            for &n in &ZIGZAG[1..] {
+                if block[n] == 0 {
+                    run += 1;
+                    continue;
+                }
+                while run > 15 {
+                    emit(1, ac, 0xf0, 0, 0);
+                    run -= 16;
+                }
+                let (size, bits) = magnitude(block[n].into());
+                emit(1, ac, run << 4 | size, bits, size);
+                run = 0;
+            }
+            if run > 0 {
+                // End of block
+                emit(1, ac, 0x00, 0, 0);
+            }
+        }
+    }
+}
+
+/// Size category and additional bits coding `value`
+fn magnitude(value: i32) -> (u8, u16) {
+    let size = 32 - value.unsigned_abs().leading_zeros();
+    // Negative values are coded as their one's complement
+    let bits = if value < 0 { value - 1 } else { value };
+    (size as u8, (bits & ((1 << size) - 1)) as u16)
+}
+
+/// Huffman table as stored in a DHT segment
+struct HuffmanTable {
+    /// Number of codes of each length from 1 to 16 bits
+    bits: [u8; 16],
+    /// Symbols ordered by code length
+    values: Vec<u8>,
+}
+
+impl HuffmanTable {
+    /// Parse a table from the data of a DHT segment after its class and number
+    ///
+    /// Returns the table and the number of bytes read.
+    fn parse(data: &[u8]) -> Result<(Self, usize)> {
+        let Some(bits) = data.get(..16) else {
+            bail!("truncated Huffman table");
+        };
+        let bits: [u8; 16] = bits.try_into()?;
+        let count = bits.iter().map(|&b| usize::from(b)).sum::<usize>();
+        let Some(values) = data.get(16..16 + count) else {
+            bail!("truncated Huffman table");
+        };
+        let table = Self {
+            bits,
+            values: values.to_vec(),
+        };
+        Ok((table, 16 + count))
+    }
+
+    /// Build an optimal table for the `frequencies` of the symbols
+    ///
+    /// This follows `jpeg_gen_optimal_table` of libjpeg, which limits codes to 16 bits
+    /// and reserves a code of ones only, which must not appear in the data.
+    fn optimal(frequencies: &[usize; 256]) -> Self {
+        let mut frequencies = frequencies.map(|f| f as u64).to_vec();
+        // Reserved symbol, which gets the longest code
+        frequencies.push(1);
+        let mut sizes = [0usize; 257];
+        let mut others = [None; 257];
+
+        // Merge the two least frequent trees until one is left
+        loop {
+            let least = |excluded: Option<usize>| {
+                (0..257)
+                    .filter(|&i| frequencies[i] > 0 && Some(i) != excluded)
+                    .min_by_key(|&i| (frequencies[i], std::cmp::Reverse(i)))
+            };
+            let Some(mut c1) = least(None) else { break };
+            let Some(mut c2) = least(Some(c1)) else { break };
+            frequencies[c1] += frequencies[c2];
+            frequencies[c2] = 0;
+
+            sizes[c1] += 1;
+            while let Some(other) = others[c1] {
+                c1 = other;
+                sizes[c1] += 1;
+            }
+            others[c1] = Some(c2);
+            sizes[c2] += 1;
+            while let Some(other) = others[c2] {
+                c2 = other;
+                sizes[c2] += 1;
+            }
+        }
+
+        let mut bits = [0u8; 33];
+        for &size in sizes.iter().filter(|&&s| s > 0) {
+            bits[size] += 1;
+        }
+        // Shorten codes longer than 16 bits by moving pairs of them up the tree
+        for i in (17..=32).rev() {
+            while bits[i] > 0 {
+                let mut j = i - 2;
+                while bits[j] == 0 {
+                    j -= 1;
+                }
+                bits[i] -= 2;
+                bits[i - 1] += 1;
+                bits[j + 1] += 2;
+                bits[j] -= 1;
+            }
+        }
+        // Remove the reserved symbol from the longest codes
+        if let Some(i) = (1..=16).rev().find(|&i| bits[i] > 0) {
+            bits[i] -= 1;
+        }
+
+        let mut values = Vec::new();
+        for size in 1..=32 {
+            values.extend((0..=255u8).filter(|&v| sizes[usize::from(v)] == size));
+        }
+        Self {
+            bits: bits[1..=16].try_into().unwrap_or_default(),
+            values,
+        }
+    }
+
+    /// Canonical code and its length of each symbol
+    fn codes(&self) -> [(u16, u8); 256] {
+        let mut codes = [(0, 0); 256];
+        let mut values = self.values.iter();
+        let mut code = 0u16;
+        for (length, &count) in (1..=16).zip(&self.bits) {
+            for value in values.by_ref().take(usize::from(count)) {
+                codes[usize::from(*value)] = (code, length);
+                code += 1;
+            }
+            code <<= 1;
+        }
+        codes
+    }
+
+    /// Lookup tables for decoding, see section F.2.2.3 of the JPEG specification
+    fn decoder(&self) -> HuffmanDecoder {
+        let mut decoder = HuffmanDecoder {
+            max_codes: [-1; 17],
+            offsets: [0; 17],
+            values: self.values.clone(),
+        };
+        let mut code = 0i32;
+        let mut index = 0i32;
+        for length in 1..=16 {
+            let count = i32::from(self.bits[length - 1]);
+            decoder.offsets[length] = index - code;
+            code += count;
+            index += count;
+            if count > 0 {
+                decoder.max_codes[length] = code - 1;
+            }
+            code <<= 1;
+        }
+        decoder
+    }
+}
+
+/// Huffman table prepared for decoding
+struct HuffmanDecoder {
+    /// Largest code of each length, -1 if there are none
+    max_codes: [i32; 17],
+    /// Offset from the codes of each length to the index of their symbol
+    offsets: [i32; 17],
+    values: Vec<u8>,
+}
+
+impl HuffmanDecoder {
+    /// Decode the next symbol
+    fn decode(&self, reader: &mut BitReader) -> Result<u8> {
+        let mut code = 0;
+        for length in 1..=16 {
+            code = code << 1 | reader.bit()?;
+            if code <= self.max_codes[length] {
+                let index = usize::try_from(code + self.offsets[length])?;
+                return self
+                    .values
+                    .get(index)
+                    .copied()
+                    .context("invalid Huffman code");
+            }
+        }
+        bail!("invalid Huffman code")
+    }
+}
+
+/// Reader of the bits of entropy-coded data
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    byte: u8,
+    /// Bits left in `byte`
+    count: u8,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            pos: 0,
+            byte: 0,
+            count: 0,
+        }
+    }
+
+    fn bit(&mut self) -> Result<i32> {
+        if self.count == 0 {
+            let Some(&byte) = self.data.get(self.pos) else {
+                bail!("truncated entropy-coded data");
+            };
+            if byte == 0xff {
+                // Data bytes of 0xff are followed by a stuffed zero, other bytes are markers
+                ensure!(
+                    self.data.get(self.pos + 1) == Some(&0),
+                    "unexpected marker in entropy-coded data"
+                );
+                self.pos += 1;
+            }
+            self.pos += 1;
+            self.byte = byte;
+            self.count = 8;
+        }
+        self.count -= 1;
+        Ok(i32::from(self.byte >> self.count & 1))
+    }
+
+    /// Receive a value coded with `size` additional bits
+    fn receive(&mut self, size: u8) -> Result<i32> {
+        let mut value = 0;
+        for _ in 0..size {
+            value = value << 1 | self.bit()?;
+        }
+        // Values below half of the range are negative
+        if size > 0 && value < 1 << (size - 1) {
+            value -= (1 << size) - 1;
+        }
+        Ok(value)
+    }
+
+    /// Skip the rest of the current byte and the following restart marker
+    fn restart(&mut self) -> Result<()> {
+        self.count = 0;
+        let marker = next_marker(self.data, &mut self.pos)?;
+        ensure!(
+            (0xd0..=0xd7).contains(&marker),
+            "missing restart marker at offset {}",
+            self.pos
+        );
+        Ok(())
+    }
+}
+
+/// Writer of entropy-coded data
+struct BitWriter {
+    out: Vec<u8>,
+    bits: u32,
+    /// Number of pending `bits`
+    count: u8,
+}
+
+impl BitWriter {
+    fn new(out: Vec<u8>) -> Self {
+        Self {
+            out,
+            bits: 0,
+            count: 0,
+        }
+    }
+
+    fn write(&mut self, bits: u16, size: u8) {
+        self.bits = self.bits << size | u32::from(bits) & ((1 << size) - 1);
+        self.count += size;
+        while self.count >= 8 {
+            self.count -= 8;
+            let byte = (self.bits >> self.count) as u8;
+            self.out.push(byte);
+            if byte == 0xff {
+                self.out.push(0);
+            }
+        }
+        self.bits &= (1 << self.count) - 1;
+    }
+
+    /// Pad the last byte with ones and return the output
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.write(0xff, 8 - self.count);
+        }
+        self.out
+    }
+}
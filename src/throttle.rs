@@ -0,0 +1,88 @@
+//! Read throttling
+//!
+//! This module limits the rate at which files are copied with a token bucket
+//! shared by all copying threads, e.g. to avoid saturating the link to a network share.
+
+use std::{
+    fs::{self, File},
+    io::{self, Read as _, Write as _},
+    path::Path,
+    sync::Mutex,
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Rate limit of reads in bytes per second
+pub(crate) struct Throttle {
+    /// Allowed bytes per second
+    rate: f64,
+    /// Maximum number of bytes read in a burst
+    capacity: f64,
+    /// Available bytes and time of the last refill
+    ///
+    /// The available bytes become negative when a read exceeds them,
+    /// which the reader makes up for by waiting.
+    bucket: Mutex<(f64, Instant)>,
+}
+
+impl Throttle {
+    /// Create a throttle allowing `bytes_per_sec` bytes per second
+    ///
+    /// Bursts are limited to a tenth of a second worth of bytes,
+    /// which keeps the rate accurate over windows of a few seconds.
+    pub(crate) fn new(bytes_per_sec: u64) -> Self {
+        let rate = bytes_per_sec.max(1) as f64;
+        let capacity = rate / 10.0;
+        Self {
+            rate,
+            capacity,
+            bucket: Mutex::new((capacity, Instant::now())),
+        }
+    }
+
+    /// Size of the chunks to copy at once
+    fn chunk_size(&self) -> usize {
+        (self.capacity as usize).clamp(1, 64 * 1024)
+    }
+
+    /// Take `bytes` from the bucket, waiting until they are available
+    fn acquire(&self, bytes: usize) {
+        let wait = {
+            let mut bucket = self.bucket.lock().unwrap_or_else(|e| e.into_inner());
+            let (available, last) = &mut *bucket;
+            let now = Instant::now();
+            *available = (*available + now.duration_since(*last).as_secs_f64() * self.rate)
+                .min(self.capacity)
+                - bytes as f64;
+            *last = now;
+            (*available < 0.0).then(|| Duration::from_secs_f64(-*available / self.rate))
+        };
+        if let Some(wait) = wait {
+            thread::sleep(wait);
+        }
+    }
+
+    /// Copy the file at `source` to `target` like [fs::copy], reading at most at the rate limit
+    pub(crate) fn copy(&self, source: &Path, target: &Path) -> io::Result<u64> {
+        let mut reader = File::open(source)?;
+        let permissions = reader.metadata()?.permissions();
+        let mut writer = File::create(target)?;
+
+        let mut buf = vec![0; self.chunk_size()];
+        let mut copied = 0;
+        loop {
+            let read = match reader.read(&mut buf) {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            self.acquire(read);
+            writer.write_all(&buf[..read])?;
+            copied += read as u64;
+        }
+
+        fs::set_permissions(target, permissions)?;
+        Ok(copied)
+    }
+}
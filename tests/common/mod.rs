@@ -0,0 +1,395 @@
+//! Helpers for the integration tests
+//!
+//! Tests run in temporary directories with small synthetic media files
+//! carrying known exif dates.
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use figment::{
+    Figment,
+    providers::{Format as _, Toml},
+};
+use media_tools::ImportConfig;
+
+/// Temporary directory, removed with all its contents when dropped
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    /// Create an empty temporary directory unique to this process and test
+    pub fn new() -> Self {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        let path = env::temp_dir().join(format!(
+            "media-tools-test-{}-{}",
+            process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+
+    /// Path of `relative` in the directory
+    pub fn join(&self, relative: &str) -> PathBuf {
+        self.0.join(relative)
+    }
+
+    /// Create the directory `relative` in the directory and return its path
+    pub fn mkdir(&self, relative: &str) -> PathBuf {
+        let path = self.join(relative);
+        fs::create_dir_all(&path).unwrap();
+        path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Tag of the creation date in the Exif IFD
+pub const DATE_TIME_ORIGINAL: u16 = 0x9003;
+/// Tag of the UTC offset of `DateTimeOriginal`, e.g. `+02:00`
+pub const OFFSET_TIME_ORIGINAL: u16 = 0x9011;
+/// Tag of the modification date in IFD0, e.g. the time of a raw conversion
+pub const MODIFY_DATE: u16 = 0x0132;
+/// Tag of the fraction of a second of `DateTimeOriginal`
+pub const SUB_SEC_TIME_ORIGINAL: u16 = 0x9291;
+
+/// Encode a minimal little-endian TIFF header with the text `tags` in the Exif IFD
+///
+/// The tags have to be sorted by their number.
+/// IFD0 holds the `orientation` if given and points to the Exif IFD.
+fn exif_with_tags(orientation: Option<u16>, tags: &[(u16, &str)]) -> Vec<u8> {
+    tiff_with_tags(orientation, &[], tags)
+}
+
+/// Encode a minimal little-endian TIFF structure with text tags in IFD0 and the Exif IFD
+///
+/// The `ifd0_tags` and `exif_tags` have to be sorted by their number,
+/// with the `ifd0_tags` between the orientation (`0x0112`) and the Exif IFD pointer (`0x8769`).
+fn tiff_with_tags(
+    orientation: Option<u16>,
+    ifd0_tags: &[(u16, &str)],
+    exif_tags: &[(u16, &str)],
+) -> Vec<u8> {
+    const IFD0: u32 = 8;
+    const ASCII: u16 = 2;
+    const SHORT: u16 = 3;
+    const LONG: u16 = 4;
+    let entries = 1 + u16::from(orientation.is_some()) + ifd0_tags.len() as u16;
+    let exif_ifd = IFD0 + 2 + 12 * u32::from(entries) + 4;
+
+    // Values longer than 4 bytes follow the Exif IFD
+    let mut offset = exif_ifd + 2 + 12 * exif_tags.len() as u32 + 4;
+    let mut data = Vec::new();
+    let mut text_entry = |tiff: &mut Vec<u8>, tag: u16, text: &str| {
+        let mut value = text.as_bytes().to_vec();
+        value.push(0);
+        tiff.extend(tag.to_le_bytes());
+        tiff.extend(ASCII.to_le_bytes());
+        tiff.extend((value.len() as u32).to_le_bytes());
+        if value.len() <= 4 {
+            // Short values are stored in the entry itself
+            value.resize(4, 0);
+            tiff.extend(value);
+        } else {
+            tiff.extend(offset.to_le_bytes());
+            offset += value.len() as u32;
+            data.extend(value);
+        }
+    };
+
+    let mut tiff = b"II*\0".to_vec();
+    tiff.extend(IFD0.to_le_bytes());
+    tiff.extend(entries.to_le_bytes());
+    if let Some(orientation) = orientation {
+        tiff.extend(0x0112u16.to_le_bytes());
+        tiff.extend(SHORT.to_le_bytes());
+        tiff.extend(1u32.to_le_bytes());
+        tiff.extend(u32::from(orientation).to_le_bytes());
+    }
+    for (tag, text) in ifd0_tags {
+        text_entry(&mut tiff, *tag, text);
+    }
+    tiff.extend(0x8769u16.to_le_bytes());
+    tiff.extend(LONG.to_le_bytes());
+    tiff.extend(1u32.to_le_bytes());
+    tiff.extend(exif_ifd.to_le_bytes());
+    tiff.extend(0u32.to_le_bytes());
+
+    tiff.extend((exif_tags.len() as u16).to_le_bytes());
+    for (tag, text) in exif_tags {
+        text_entry(&mut tiff, *tag, text);
+    }
+    tiff.extend(0u32.to_le_bytes());
+    tiff.extend(data);
+    tiff
+}
+
+/// Write a minimal TIFF-based raw file like a DNG to `path`
+///
+/// See [tiff_with_tags] for the `ifd0_tags` and `exif_tags`.
+/// The tags are followed by blank image data, which the parser expects after the Exif IFD.
+pub fn write_tiff(path: &Path, ifd0_tags: &[(u16, &str)], exif_tags: &[(u16, &str)]) {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).unwrap();
+    }
+    let mut tiff = tiff_with_tags(None, ifd0_tags, exif_tags);
+    tiff.resize(tiff.len() + 4096, 0);
+    fs::write(path, tiff).unwrap();
+}
+
+/// Write a minimal JPEG to `path` taken at `date`, e.g. `2024:03:15 10:00:00`
+///
+/// The image data is extended by `padding` zero bytes,
+/// so that files of the same date can differ in size and content.
+pub fn write_jpeg(path: &Path, date: &str, padding: usize) {
+    write_jpeg_with_tags(path, &[(DATE_TIME_ORIGINAL, date)], padding);
+}
+
+/// Write a minimal JPEG to `path` with the text `tags` in its Exif IFD, sorted by number
+///
+/// See [write_jpeg].
+pub fn write_jpeg_with_tags(path: &Path, tags: &[(u16, &str)], padding: usize) {
+    let mut app1 = b"Exif\0\0".to_vec();
+    app1.extend(exif_with_tags(None, tags));
+
+    let mut jpeg = vec![0xff, 0xd8, 0xff, 0xe1];
+    jpeg.extend((app1.len() as u16 + 2).to_be_bytes());
+    jpeg.extend(app1);
+    jpeg.extend([0xff, 0xda, 0x00, 0x08]);
+    jpeg.extend(vec![0; 64 + padding]);
+    jpeg.extend([0xff, 0xd9]);
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).unwrap();
+    }
+    fs::write(path, jpeg).unwrap();
+}
+
+/// Write a minimal MP4 video to `path`, created `created` seconds after the Unix epoch
+///
+/// The creation time is stored in UTC in the movie header, like most cameras do.
+pub fn write_mp4(path: &Path, created: u32) {
+    /// Seconds from 1904, the epoch of MP4 timestamps, to 1970
+    const MP4_EPOCH_OFFSET: u32 = 2_082_844_800;
+
+    let mut mvhd = vec![0; 4];
+    mvhd.extend((created + MP4_EPOCH_OFFSET).to_be_bytes());
+    mvhd.extend((created + MP4_EPOCH_OFFSET).to_be_bytes());
+    mvhd.extend(1000u32.to_be_bytes());
+    mvhd.extend(0u32.to_be_bytes());
+    mvhd.extend(0x0001_0000u32.to_be_bytes());
+    mvhd.extend(0x0100u16.to_be_bytes());
+    mvhd.extend([0; 10]);
+    for value in [0x0001_0000u32, 0, 0, 0, 0x0001_0000, 0, 0, 0, 0x4000_0000] {
+        mvhd.extend(value.to_be_bytes());
+    }
+    mvhd.extend([0; 24]);
+    mvhd.extend(2u32.to_be_bytes());
+
+    let mut mp4 = mp4_box(b"ftyp", b"isom\0\0\x02\0isomiso2mp41");
+    mp4.extend(mp4_box(b"moov", &mp4_box(b"mvhd", &mvhd)));
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).unwrap();
+    }
+    fs::write(path, mp4).unwrap();
+}
+
+/// Write a minimal HEIC image to `path` taken at `date`, e.g. `2024:03:15 10:00:00`
+///
+/// The Exif data is an item in the media data box, located by the `iinf` and `iloc` boxes.
+/// There is no image item, as the date is all the import reads.
+pub fn write_heic(path: &Path, date: &str) {
+    let mut exif = 6u32.to_be_bytes().to_vec();
+    exif.extend(b"Exif\0\0");
+    exif.extend(exif_with_tags(None, &[(DATE_TIME_ORIGINAL, date)]));
+
+    // Item 1 of type `Exif`, version 2 of the item info entry
+    let mut infe = vec![2, 0, 0, 0];
+    infe.extend(1u16.to_be_bytes());
+    infe.extend(0u16.to_be_bytes());
+    infe.extend(b"Exif\0");
+    let mut iinf = vec![0; 4];
+    iinf.extend(1u16.to_be_bytes());
+    iinf.extend(mp4_box(b"infe", &infe));
+
+    let meta = |exif_offset: u32| {
+        // Version 0 with 4 byte offsets and lengths, no base offsets and one extent
+        let mut iloc = vec![0, 0, 0, 0, 0x44, 0x00];
+        iloc.extend(1u16.to_be_bytes());
+        iloc.extend(1u16.to_be_bytes());
+        iloc.extend(0u16.to_be_bytes());
+        iloc.extend(1u16.to_be_bytes());
+        iloc.extend(exif_offset.to_be_bytes());
+        iloc.extend((exif.len() as u32).to_be_bytes());
+
+        let mut meta = vec![0; 4];
+        meta.extend(mp4_box(b"iinf", &iinf));
+        meta.extend(mp4_box(b"iloc", &iloc));
+        mp4_box(b"meta", &meta)
+    };
+
+    let mut heic = mp4_box(b"ftyp", b"heic\0\0\0\0mif1heic");
+    let exif_offset = heic.len() + meta(0).len() + 8;
+    heic.extend(meta(exif_offset as u32));
+    heic.extend(mp4_box(b"mdat", &exif));
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).unwrap();
+    }
+    fs::write(path, heic).unwrap();
+}
+
+/// Encode an MP4 box of `kind` with `content`
+fn mp4_box(kind: &[u8; 4], content: &[u8]) -> Vec<u8> {
+    let mut mp4_box = (content.len() as u32 + 8).to_be_bytes().to_vec();
+    mp4_box.extend(kind);
+    mp4_box.extend(content);
+    mp4_box
+}
+
+/// Write a JPEG photo of `width` x `height` pixels with the exif `orientation` to `path`
+///
+/// Unlike [write_jpeg], the image data is a decodable color gradient.
+#[cfg(feature = "auto-rotate")]
+pub fn write_photo(path: &Path, date: &str, (width, height): (u32, u32), orientation: u16) {
+    use image::{ImageEncoder as _, codecs::jpeg::JpegEncoder};
+
+    let pixels: Vec<_> = (0..height)
+        .flat_map(|y| (0..width).flat_map(move |x| [(x * 8) as u8, (y * 8) as u8, 128]))
+        .collect();
+    let mut encoded = Vec::new();
+    JpegEncoder::new_with_quality(&mut encoded, 90)
+        .write_image(&pixels, width, height, image::ExtendedColorType::Rgb8)
+        .unwrap();
+
+    let mut app1 = b"Exif\0\0".to_vec();
+    app1.extend(exif_with_tags(
+        Some(orientation),
+        &[(DATE_TIME_ORIGINAL, date)],
+    ));
+    let mut jpeg = encoded[..2].to_vec();
+    jpeg.extend([0xff, 0xe1]);
+    jpeg.extend((app1.len() as u16 + 2).to_be_bytes());
+    jpeg.extend(app1);
+    jpeg.extend(&encoded[2..]);
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).unwrap();
+    }
+    fs::write(path, jpeg).unwrap();
+}
+
+/// Write a JPEG file taken at `date` with the exif `orientation` and `segments` to `path`
+///
+/// The segments are given as marker and data without their length, ending with the scan
+/// header. Its entropy-coded `scan` data is written as is, so that it may be corrupted.
+#[cfg(feature = "auto-rotate")]
+pub fn write_jpeg_segments(
+    path: &Path,
+    date: &str,
+    orientation: u16,
+    segments: &[(u8, &[u8])],
+    scan: &[u8],
+) {
+    let mut app1 = b"Exif\0\0".to_vec();
+    app1.extend(exif_with_tags(
+        Some(orientation),
+        &[(DATE_TIME_ORIGINAL, date)],
+    ));
+
+    let mut jpeg = vec![0xff, 0xd8];
+    for (marker, data) in [(0xe1, app1.as_slice())].iter().chain(segments) {
+        jpeg.extend([0xff, *marker]);
+        jpeg.extend((data.len() as u16 + 2).to_be_bytes());
+        jpeg.extend(*data);
+    }
+    jpeg.extend(scan);
+    jpeg.extend([0xff, 0xd9]);
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).unwrap();
+    }
+    fs::write(path, jpeg).unwrap();
+}
+
+/// Write a grayscale JPEG image of 9x8 blocks getting darker to the right to `path`
+///
+/// The first `reversed_rows` rows get brighter to the right instead,
+/// which flips the 8 bits of each row in the difference hash of the image.
+#[cfg(feature = "perceptual-hash")]
+pub fn write_gradient(path: &Path, reversed_rows: u32) {
+    use image::{ImageEncoder as _, codecs::jpeg::JpegEncoder};
+
+    let pixels: Vec<_> = (0..64)
+        .flat_map(|y| {
+            (0..72).map(move |x| {
+                let column = if y / 8 < reversed_rows {
+                    x / 8
+                } else {
+                    8 - x / 8
+                };
+                (32 + column * 24) as u8
+            })
+        })
+        .collect();
+    let mut encoded = Vec::new();
+    JpegEncoder::new_with_quality(&mut encoded, 100)
+        .write_image(&pixels, 72, 64, image::ExtendedColorType::L8)
+        .unwrap();
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).unwrap();
+    }
+    fs::write(path, encoded).unwrap();
+}
+
+/// Configuration importing `jpg` files from `search` to `output`, with `existing` indexed
+///
+/// Further settings are given as lines of TOML in `extra`, which may also override these.
+pub fn config(existing: &Path, search: &Path, output: &Path, extra: &str) -> ImportConfig {
+    let toml = format!(
+        "extensions = ['jpg']\n\
+         existing_paths = ['{}']\n\
+         search_paths = ['{}']\n\
+         output_path = '{}'",
+        existing.display(),
+        search.display(),
+        output.display(),
+    );
+    let figment = Figment::from(Toml::string(&toml)).merge(Toml::string(extra));
+    ImportConfig::try_from_figment(figment).unwrap()
+}
+
+/// Sorted paths of all files below `root`, relative to it with `/` as separator
+pub fn files_below(root: &Path) -> Vec<String> {
+    fn walk(root: &Path, dir: &Path, files: &mut Vec<String>) {
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                walk(root, &path, files);
+            } else {
+                let relative = path.strip_prefix(root).unwrap();
+                let components: Vec<_> = relative
+                    .components()
+                    .map(|c| c.as_os_str().to_string_lossy().into_owned())
+                    .collect();
+                files.push(components.join("/"));
+            }
+        }
+    }
+
+    let mut files = Vec::new();
+    walk(root, root, &mut files);
+    files.sort();
+    files
+}
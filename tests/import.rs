@@ -0,0 +1,1095 @@
+//! End-to-end tests of importing media files
+
+mod common;
+
+use std::{
+    fs::{self, File, FileTimes},
+    time::{Duration, SystemTime},
+};
+
+use common::{
+    DATE_TIME_ORIGINAL, MODIFY_DATE, OFFSET_TIME_ORIGINAL, SUB_SEC_TIME_ORIGINAL, TempDir, config,
+    files_below, write_heic, write_jpeg, write_jpeg_with_tags, write_mp4, write_tiff,
+};
+use media_tools::{
+    ImportDecision, MediaFile, SkipReason, count_media_files, import_media_files, plan_import,
+};
+
+#[test]
+fn imports_into_date_directories() {
+    let dir = TempDir::new();
+    let existing = dir.mkdir("existing");
+    let search = dir.mkdir("search");
+    let output = dir.join("output");
+    write_jpeg(&search.join("IMG_1.jpg"), "2024:03:15 10:00:00", 0);
+    write_jpeg(&search.join("nested/IMG_2.jpg"), "2024:03:16 11:30:00", 0);
+
+    let stats = import_media_files(&config(&existing, &search, &output, "")).unwrap();
+
+    assert_eq!(stats.found, 2);
+    assert_eq!(stats.copied, 2);
+    assert_eq!(stats.skipped, 0);
+    assert_eq!(
+        files_below(&output),
+        ["2024_03_15/IMG_1.jpg", "2024_03_16/IMG_2.jpg"]
+    );
+    assert_eq!(
+        fs::read(output.join("2024_03_15/IMG_1.jpg")).unwrap(),
+        fs::read(search.join("IMG_1.jpg")).unwrap()
+    );
+}
+
+#[test]
+fn matches_extensions_ignoring_case() {
+    let dir = TempDir::new();
+    let existing = dir.mkdir("existing");
+    let search = dir.mkdir("search");
+    let output = dir.join("output");
+    write_jpeg(&search.join("IMG_1.JPG"), "2024:03:15 10:00:00", 0);
+    write_jpeg(&search.join("IMG_2.Jpg"), "2024:03:15 10:01:00", 0);
+    write_jpeg(&search.join("IMG_3.jpg"), "2024:03:15 10:02:00", 0);
+    write_jpeg(&search.join("IMG_4.JPEG"), "2024:03:15 10:03:00", 0);
+
+    let stats = import_media_files(&config(&existing, &search, &output, "")).unwrap();
+
+    assert_eq!(stats.found, 3);
+    assert_eq!(
+        files_below(&output),
+        [
+            "2024_03_15/IMG_1.JPG",
+            "2024_03_15/IMG_2.Jpg",
+            "2024_03_15/IMG_3.jpg"
+        ]
+    );
+}
+
+#[test]
+fn skips_duplicates_of_existing_files() {
+    let dir = TempDir::new();
+    let existing = dir.mkdir("existing");
+    let search = dir.mkdir("search");
+    let output = dir.join("output");
+    write_jpeg(&existing.join("2024/IMG_1.jpg"), "2024:03:15 10:00:00", 0);
+    write_jpeg(&search.join("IMG_1.jpg"), "2024:03:15 10:00:00", 0);
+    write_jpeg(&search.join("IMG_2.jpg"), "2024:03:15 10:05:00", 0);
+
+    let stats = import_media_files(&config(&existing, &search, &output, "")).unwrap();
+
+    assert_eq!(stats.copied, 1);
+    assert_eq!(stats.skipped, 1);
+    assert_eq!(stats.duplicates.len(), 1);
+    assert_eq!(stats.duplicates[0].source, search.join("IMG_1.jpg"));
+    assert_eq!(
+        stats.duplicates[0].existing,
+        existing.join("2024/IMG_1.jpg")
+    );
+    assert_eq!(stats.duplicates[0].reason, SkipReason::Duplicate);
+    assert_eq!(files_below(&output), ["2024_03_15/IMG_2.jpg"]);
+}
+
+#[test]
+fn skips_duplicates_with_names_in_another_case() {
+    let dir = TempDir::new();
+    let existing = dir.mkdir("existing");
+    let search = dir.mkdir("search");
+    let output = dir.join("output");
+    write_jpeg(&existing.join("2024/IMG_1.JPG"), "2024:03:15 10:00:00", 0);
+    write_jpeg(&search.join("img_1.jpg"), "2024:03:15 10:00:00", 0);
+
+    let stats = import_media_files(&config(&existing, &search, &output, "")).unwrap();
+
+    assert_eq!(stats.copied, 0);
+    assert_eq!(stats.name_existing, 1);
+    assert_eq!(stats.duplicates.len(), 1);
+    assert_eq!(
+        stats.duplicates[0].existing,
+        existing.join("2024/IMG_1.JPG")
+    );
+    assert_eq!(stats.duplicates[0].reason, SkipReason::Duplicate);
+}
+
+#[test]
+fn keeps_files_with_other_names_of_the_same_size_and_date() {
+    let dir = TempDir::new();
+    let existing = dir.mkdir("existing");
+    let search = dir.mkdir("search");
+    let output = dir.join("output");
+    write_jpeg(&existing.join("2024/IMG_1.jpg"), "2024:03:15 10:00:00", 0);
+    write_jpeg(&search.join("IMG_2.jpg"), "2024:03:15 10:00:00", 0);
+
+    let stats = import_media_files(&config(&existing, &search, &output, "")).unwrap();
+
+    assert_eq!(stats.copied, 1);
+    assert_eq!(stats.skipped, 0);
+    assert!(stats.duplicates.is_empty());
+    assert_eq!(files_below(&existing), ["2024/IMG_1.jpg"]);
+    assert_eq!(files_below(&output), ["2024_03_15/IMG_2.jpg"]);
+}
+
+#[test]
+fn counts_existing_files_with_unparseable_exif() {
+    let dir = TempDir::new();
+    let existing = dir.mkdir("existing");
+    let search = dir.mkdir("search");
+    let output = dir.join("output");
+    // Exif data whose IFD0 lies beyond the end of the segment
+    let mut jpeg = vec![0xff, 0xd8, 0xff, 0xe1, 0x00, 0x10];
+    jpeg.extend(b"Exif\0\0II*\0\xff\xff\x00\x00");
+    jpeg.extend([0xff, 0xda, 0x00, 0x08]);
+    jpeg.extend([0; 64]);
+    jpeg.extend([0xff, 0xd9]);
+    fs::write(existing.join("IMG_1.jpg"), &jpeg).unwrap();
+    fs::write(existing.join("IMG_2.jpg"), &jpeg).unwrap();
+    fs::write(search.join("IMG_1.jpg"), &jpeg).unwrap();
+
+    let stats = import_media_files(&config(&existing, &search, &output, "")).unwrap();
+
+    assert_eq!(stats.parse_failed, 2);
+    // The files are still found by name and size
+    assert_eq!(stats.indexed, 2);
+    assert_eq!(stats.duplicates[0].reason, SkipReason::Duplicate);
+}
+
+#[test]
+fn replaces_lower_quality_versions() {
+    let dir = TempDir::new();
+    let existing = dir.mkdir("existing");
+    let search = dir.mkdir("search");
+    let output = dir.join("output");
+    write_jpeg(&existing.join("IMG_1.jpg"), "2024:03:15 10:00:00", 0);
+    write_jpeg(&search.join("IMG_1.jpg"), "2024:03:15 10:00:00", 100);
+
+    let stats = import_media_files(&config(&existing, &search, &output, "")).unwrap();
+
+    assert_eq!(stats.copied, 1);
+    assert_eq!(stats.copied_hq, 1);
+    assert_eq!(stats.replaced, [existing.join("IMG_1.jpg")]);
+    assert_eq!(files_below(&output), ["2024_03_15/IMG_1.jpg"]);
+}
+
+#[test]
+fn resolves_name_collisions_with_suffix() {
+    let dir = TempDir::new();
+    let existing = dir.mkdir("existing");
+    let search = dir.mkdir("search");
+    let output = dir.join("output");
+    // Different shots of the same name and day, e.g. from two cameras,
+    // which differ in size as files of the same name and size are duplicates
+    write_jpeg(
+        &output.join("2024_03_15/IMG_1.jpg"),
+        "2024:03:15 08:00:00",
+        0,
+    );
+    write_jpeg(&search.join("IMG_1.jpg"), "2024:03:15 10:00:00", 10);
+
+    let stats = import_media_files(&config(&existing, &search, &output, "")).unwrap();
+
+    assert_eq!(stats.copied, 1);
+    assert_eq!(stats.ambiguous.len(), 1);
+    assert_eq!(
+        files_below(&output),
+        ["2024_03_15/IMG_1.jpg", "2024_03_15/IMG_1_1.jpg"]
+    );
+    assert_eq!(
+        fs::read(output.join("2024_03_15/IMG_1_1.jpg")).unwrap(),
+        fs::read(search.join("IMG_1.jpg")).unwrap()
+    );
+}
+
+#[test]
+fn buckets_around_midnight_in_the_bucket_timezone() {
+    let dir = TempDir::new();
+    let existing = dir.mkdir("existing");
+    let search = dir.mkdir("search");
+    let before = [
+        (DATE_TIME_ORIGINAL, "2024:03:15 23:30:00"),
+        (OFFSET_TIME_ORIGINAL, "+00:00"),
+    ];
+    let after = [
+        (DATE_TIME_ORIGINAL, "2024:03:16 00:30:00"),
+        (OFFSET_TIME_ORIGINAL, "+02:00"),
+    ];
+    write_jpeg_with_tags(&search.join("BEFORE.jpg"), &before, 0);
+    write_jpeg_with_tags(&search.join("AFTER.jpg"), &after, 0);
+
+    // Without a timezone, each file is bucketed by its own offset
+    let output = dir.join("offset");
+    import_media_files(&config(&existing, &search, &output, "")).unwrap();
+    assert_eq!(
+        files_below(&output),
+        ["2024_03_15/BEFORE.jpg", "2024_03_16/AFTER.jpg"]
+    );
+
+    // 23:30 UTC is past midnight in Berlin (UTC+1)
+    let output = dir.join("berlin");
+    let extra = "bucket_timezone = 'Europe/Berlin'";
+    import_media_files(&config(&existing, &search, &output, extra)).unwrap();
+    assert_eq!(
+        files_below(&output),
+        ["2024_03_15/AFTER.jpg", "2024_03_16/BEFORE.jpg"]
+    );
+
+    // 00:30 UTC+2 is before midnight in UTC
+    let output = dir.join("utc");
+    let extra = "bucket_timezone = 'UTC'";
+    import_media_files(&config(&existing, &search, &output, extra)).unwrap();
+    assert_eq!(
+        files_below(&output),
+        ["2024_03_15/AFTER.jpg", "2024_03_15/BEFORE.jpg"]
+    );
+}
+
+#[test]
+fn single_threaded_runs_produce_the_same_layout() {
+    let dir = TempDir::new();
+    let existing = dir.mkdir("existing");
+    let search = dir.mkdir("search");
+    // Different files with the same name and day collide in the date directory
+    for (i, subdir) in ["c", "a", "d", "b"].iter().enumerate() {
+        write_jpeg(
+            &search.join(subdir).join("IMG_1.jpg"),
+            &format!("2024:03:15 10:0{i}:00"),
+            i * 10,
+        );
+    }
+
+    let outputs = [dir.join("first"), dir.join("second")];
+    let layouts = outputs.map(|output| {
+        let extra = "index_threads = 1\ncopy_threads = 1";
+        import_media_files(&config(&existing, &search, &output, extra)).unwrap();
+        files_below(&output)
+            .into_iter()
+            .map(|file| {
+                let content = fs::read(output.join(&file)).unwrap();
+                (file, content)
+            })
+            .collect::<Vec<_>>()
+    });
+
+    assert_eq!(layouts[0].len(), 4);
+    assert_eq!(layouts[0], layouts[1]);
+    // Files are processed sorted by path, so the first one keeps its name
+    assert_eq!(
+        layouts[0][0],
+        (
+            "2024_03_15/IMG_1.jpg".to_owned(),
+            fs::read(search.join("a/IMG_1.jpg")).unwrap()
+        )
+    );
+}
+
+#[test]
+fn shifts_video_timestamps_by_the_video_utc_offset() {
+    let dir = TempDir::new();
+    let existing = dir.mkdir("existing");
+    let search = dir.mkdir("search");
+    // 2024-03-15 23:30:00 UTC
+    write_mp4(&search.join("VID_1.mp4"), 1_710_545_400);
+
+    let output = dir.join("utc");
+    import_media_files(&config(&existing, &search, &output, "extensions = ['mp4']")).unwrap();
+    assert_eq!(files_below(&output), ["2024_03_15/VID_1.mp4"]);
+
+    let output = dir.join("shifted");
+    let extra = "extensions = ['mp4']\nvideo_utc_offset = 120";
+    import_media_files(&config(&existing, &search, &output, extra)).unwrap();
+    assert_eq!(files_below(&output), ["2024_03_16/VID_1.mp4"]);
+}
+
+#[test]
+fn dates_heic_files_by_their_exif_item() {
+    let dir = TempDir::new();
+    let existing = dir.mkdir("existing");
+    let search = dir.mkdir("search");
+    let output = dir.join("output");
+    write_heic(&search.join("IMG_1.heic"), "2024:03:15 10:00:00");
+
+    let extra = "extensions = ['heic']";
+    let stats = import_media_files(&config(&existing, &search, &output, extra)).unwrap();
+
+    assert_eq!(stats.copied, 1);
+    assert_eq!(files_below(&output), ["2024_03_15/IMG_1.heic"]);
+}
+
+#[test]
+fn dates_dng_files_by_their_capture_time() {
+    let dir = TempDir::new();
+    let existing = dir.mkdir("existing");
+    let search = dir.mkdir("search");
+    let output = dir.join("output");
+    // Converted to DNG days after the capture
+    write_tiff(
+        &search.join("IMG_1.dng"),
+        &[(MODIFY_DATE, "2024:03:20 18:00:00")],
+        &[(DATE_TIME_ORIGINAL, "2024:03:15 10:00:00")],
+    );
+
+    let extra = "extensions = ['dng']";
+    let stats = import_media_files(&config(&existing, &search, &output, extra)).unwrap();
+
+    assert_eq!(stats.copied, 1);
+    assert_eq!(files_below(&output), ["2024_03_15/IMG_1.dng"]);
+}
+
+#[test]
+fn nests_month_directories_in_year_directories() {
+    let dir = TempDir::new();
+    let existing = dir.mkdir("existing");
+    let search = dir.mkdir("search");
+    let output = dir.join("output");
+    write_jpeg(&search.join("IMG_1.jpg"), "2024:03:15 10:00:00", 0);
+    write_jpeg(&search.join("IMG_2.jpg"), "2024:03:16 11:30:00", 0);
+
+    let config = config(&existing, &search, &output, "bucket_depth = 'month'");
+    import_media_files(&config).unwrap();
+
+    assert_eq!(
+        files_below(&output),
+        ["2024/2024-03/IMG_1.jpg", "2024/2024-03/IMG_2.jpg"]
+    );
+}
+
+#[test]
+fn resolves_name_collisions_with_hash_suffix() {
+    let dir = TempDir::new();
+    let existing = dir.mkdir("existing");
+    let search = dir.mkdir("search");
+    let output = dir.join("output");
+    write_jpeg(
+        &output.join("2024_03_15/IMG_1.jpg"),
+        "2024:03:15 08:00:00",
+        0,
+    );
+    write_jpeg(&search.join("IMG_1.jpg"), "2024:03:15 10:00:00", 10);
+    let hash = blake3::hash(&fs::read(search.join("IMG_1.jpg")).unwrap());
+    let name = format!("2024_03_15/IMG_1_{}.jpg", &hash.to_hex()[..8]);
+
+    let config = config(
+        &existing,
+        &search,
+        &output,
+        "collision_strategy = 'hash-suffix'",
+    );
+    let first = import_media_files(&config).unwrap();
+    let second = import_media_files(&config).unwrap();
+
+    assert_eq!(first.copied, 1);
+    assert_eq!(second.copied, 0);
+    assert_eq!(
+        files_below(&output),
+        ["2024_03_15/IMG_1.jpg", name.as_str()]
+    );
+}
+
+#[test]
+fn repeated_import_copies_nothing() {
+    let dir = TempDir::new();
+    let existing = dir.mkdir("existing");
+    let search = dir.mkdir("search");
+    let output = dir.join("output");
+    write_jpeg(&search.join("IMG_1.jpg"), "2024:03:15 10:00:00", 0);
+    write_jpeg(&search.join("IMG_2.jpg"), "2024:03:16 10:00:00", 0);
+    let config = config(&existing, &search, &output, "");
+
+    let first = import_media_files(&config).unwrap();
+    let second = import_media_files(&config).unwrap();
+
+    assert_eq!(first.copied, 2);
+    assert_eq!(second.copied, 0);
+    assert_eq!(second.skipped, 2);
+    assert_eq!(
+        files_below(&output),
+        ["2024_03_15/IMG_1.jpg", "2024_03_16/IMG_2.jpg"]
+    );
+}
+
+#[test]
+fn groups_only_dated_files_into_bursts() {
+    let dir = TempDir::new();
+    let existing = dir.mkdir("existing");
+    let search = dir.mkdir("search");
+    let output = dir.join("output");
+    write_jpeg(&search.join("IMG_1.jpg"), "2024:03:15 10:00:00", 0);
+    write_jpeg(&search.join("IMG_2.jpg"), "2024:03:15 10:00:02", 0);
+    // JPEGs without exif data, undated with `exif-only`
+    let mut jpeg = vec![0xff, 0xd8, 0xff, 0xda, 0x00, 0x08];
+    jpeg.extend([0; 64]);
+    jpeg.extend([0xff, 0xd9]);
+    fs::write(search.join("IMG_3.jpg"), &jpeg).unwrap();
+    fs::write(search.join("IMG_4.jpg"), &jpeg).unwrap();
+
+    let extra = "burst_window_secs = 5\ndate_strategy = 'exif-only'";
+    let stats = import_media_files(&config(&existing, &search, &output, extra)).unwrap();
+
+    assert_eq!(stats.copied, 4);
+    assert_eq!(
+        files_below(&output),
+        [
+            "2024_03_15/burst_100000/IMG_1.jpg",
+            "2024_03_15/burst_100000/IMG_2.jpg",
+            "_undated/IMG_3.jpg",
+            "_undated/IMG_4.jpg",
+        ]
+    );
+}
+
+#[test]
+fn transfers_sidecars_with_their_media_files() {
+    let dir = TempDir::new();
+    let existing = dir.mkdir("existing");
+    let search = dir.mkdir("search");
+    let output = dir.join("output");
+    write_jpeg(&search.join("IMG_1.jpg"), "2024:03:15 10:00:00", 0);
+    write_jpeg(&search.join("IMG_2.jpg"), "2024:03:15 10:05:00", 0);
+    // Sidecars are named after the stem or the full name of their media file
+    fs::write(search.join("IMG_1.xmp"), "xmp").unwrap();
+    fs::write(search.join("IMG_1.jpg.aae"), "aae").unwrap();
+    fs::write(search.join("IMG_3.xmp"), "xmp").unwrap();
+
+    let extra = "include_sidecars = ['xmp', 'aae']";
+    let stats = import_media_files(&config(&existing, &search, &output, extra)).unwrap();
+
+    assert_eq!(stats.copied, 2);
+    assert_eq!(
+        files_below(&output),
+        [
+            "2024_03_15/IMG_1.jpg",
+            "2024_03_15/IMG_1.jpg.aae",
+            "2024_03_15/IMG_1.xmp",
+            "2024_03_15/IMG_2.jpg",
+        ]
+    );
+}
+
+#[test]
+fn counts_copies_per_device() {
+    const MAKE: u16 = 0x010f;
+    const MODEL: u16 = 0x0110;
+
+    let dir = TempDir::new();
+    let existing = dir.mkdir("existing");
+    let search = dir.mkdir("search");
+    let output = dir.join("output");
+    for (name, make, model) in [
+        ("IMG_1.jpg", "Canon", "Canon EOS R6"),
+        ("IMG_2.jpg", "Canon", "Canon EOS R6"),
+        ("IMG_3.jpg", "Apple", "iPhone 13"),
+    ] {
+        let tags = [
+            (MAKE, make),
+            (MODEL, model),
+            (DATE_TIME_ORIGINAL, "2024:03:15 10:00:00"),
+        ];
+        write_jpeg_with_tags(&search.join(name), &tags, 0);
+    }
+
+    let extra = "dry_run = true";
+    let stats = import_media_files(&config(&existing, &search, &output, extra)).unwrap();
+
+    assert_eq!(stats.copied_per_device.len(), 2);
+    assert_eq!(stats.copied_per_device["Canon EOS R6"], 2);
+    assert_eq!(stats.copied_per_device["Apple iPhone 13"], 1);
+}
+
+#[test]
+fn reuses_the_index_cache_of_unchanged_files() {
+    let dir = TempDir::new();
+    let existing = dir.mkdir("existing");
+    let search = dir.mkdir("search");
+    let output = dir.join("output");
+    write_jpeg(&existing.join("IMG_1.jpg"), "2024:03:15 10:00:00", 0);
+    write_jpeg(&existing.join("IMG_2.jpg"), "2024:03:15 10:05:00", 0);
+    write_jpeg(&search.join("COPY.jpg"), "2024:03:15 10:00:00", 0);
+
+    let extra = format!(
+        "index_cache = '{}'\ndedup_by_content = true",
+        dir.join("index.json").display()
+    );
+    let config = config(&existing, &search, &output, &extra);
+    let cold = import_media_files(&config).unwrap();
+    let warm = import_media_files(&config).unwrap();
+
+    assert_eq!(cold.index_parsed, 2);
+    assert_eq!(warm.indexed, 2);
+    assert_eq!(warm.index_parsed, 0);
+    // The cached content hashes still find the renamed duplicate
+    assert_eq!(warm.copied, 0);
+    assert_eq!(warm.duplicates.len(), 1);
+}
+
+#[test]
+fn discards_the_index_cache_of_other_date_settings() {
+    let dir = TempDir::new();
+    let existing = dir.mkdir("existing");
+    let search = dir.mkdir("search");
+    let output = dir.join("output");
+    write_jpeg(&existing.join("IMG_1.jpg"), "2024:03:15 10:00:00", 0);
+
+    let cache = format!("index_cache = '{}'", dir.join("index.json").display());
+    let subseconds = format!("{cache}\nsubsecond_precision = true");
+    let cold = import_media_files(&config(&existing, &search, &output, &cache)).unwrap();
+    let changed = import_media_files(&config(&existing, &search, &output, &subseconds)).unwrap();
+    let warm = import_media_files(&config(&existing, &search, &output, &subseconds)).unwrap();
+
+    assert_eq!(cold.index_parsed, 1);
+    assert_eq!(changed.index_parsed, 1);
+    assert_eq!(warm.index_parsed, 0);
+}
+
+#[test]
+fn skips_files_already_at_their_target() {
+    let dir = TempDir::new();
+    let existing = dir.mkdir("existing");
+    let output = dir.join("output");
+    // The search path is a date directory of the output path
+    let search = output.join("2024_03_15");
+    let source = search.join("IMG_1.jpg");
+    write_jpeg(&source, "2024:03:15 10:00:00", 100);
+    let content = fs::read(&source).unwrap();
+
+    // Without indexing the output, the file is not found as a duplicate of itself
+    let config = config(&existing, &search, &output, "index_output = false");
+    let decisions: Vec<_> = plan_import(&config)
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(
+        decisions,
+        [ImportDecision::Skip {
+            source: source.clone(),
+            reason: SkipReason::SourceIsTarget,
+            existing: None,
+        }]
+    );
+
+    let stats = import_media_files(&config).unwrap();
+
+    assert_eq!(stats.copied, 0);
+    assert_eq!(stats.skipped, 1);
+    assert_eq!(stats.errors, 0);
+    assert_eq!(files_below(&output), ["2024_03_15/IMG_1.jpg"]);
+    assert_eq!(fs::read(&source).unwrap(), content);
+}
+
+#[test]
+fn ignores_the_output_nested_in_a_search_path() {
+    let dir = TempDir::new();
+    let existing = dir.mkdir("existing");
+    let search = dir.mkdir("search");
+    let output = search.join("sorted");
+    write_jpeg(&search.join("IMG_1.jpg"), "2024:03:15 10:00:00", 0);
+
+    let config = config(&existing, &search, &output, "");
+    let first = import_media_files(&config).unwrap();
+    let second = import_media_files(&config).unwrap();
+
+    assert_eq!(first.found, 1);
+    assert_eq!(first.copied, 1);
+    // The copy in the output is not found again
+    assert_eq!(second.found, 1);
+    assert_eq!(second.copied, 0);
+    assert_eq!(files_below(&output), ["2024_03_15/IMG_1.jpg"]);
+}
+
+#[test]
+fn counts_only_actual_copies_towards_the_limit() {
+    let dir = TempDir::new();
+    let existing = dir.mkdir("existing");
+    let search = dir.mkdir("search");
+    let output = dir.mkdir("output");
+    // A file in place of its date directory fails the first copy
+    fs::write(output.join("2024_03_15"), "").unwrap();
+    write_jpeg(&search.join("A.jpg"), "2024:03:15 10:00:00", 0);
+    write_jpeg(&search.join("B.jpg"), "2024:03:16 10:00:00", 0);
+    write_jpeg(&search.join("C.jpg"), "2024:03:17 10:00:00", 0);
+
+    for extra in ["limit = 1", "limit = 1\ncopy_threads = 2"] {
+        let stats = import_media_files(&config(&existing, &search, &output, extra)).unwrap();
+
+        assert_eq!(stats.errors, 1);
+        assert_eq!(stats.copied, 1);
+        assert_eq!(stats.remaining, 1);
+        assert_eq!(files_below(&output), ["2024_03_15", "2024_03_16/B.jpg"]);
+        fs::remove_dir_all(output.join("2024_03_16")).unwrap();
+    }
+}
+
+#[cfg(feature = "perceptual-hash")]
+#[test]
+fn skips_near_duplicates_below_the_perceptual_hash_threshold() {
+    use common::write_gradient;
+
+    let dir = TempDir::new();
+    let existing = dir.mkdir("existing");
+    let search = dir.mkdir("search");
+    write_gradient(&existing.join("IMG_1.jpg"), 0);
+    // The hashes differ in exactly 8 bits
+    write_gradient(&search.join("EXPORT.jpg"), 1);
+
+    let output = dir.join("at");
+    let extra = "perceptual_hash_threshold = 8";
+    let stats = import_media_files(&config(&existing, &search, &output, extra)).unwrap();
+    assert_eq!(stats.copied, 1);
+
+    let output = dir.join("above");
+    let extra = "perceptual_hash_threshold = 9";
+    let stats = import_media_files(&config(&existing, &search, &output, extra)).unwrap();
+    assert_eq!(stats.copied, 0);
+    assert_eq!(stats.duplicates[0].reason, SkipReason::NearDuplicate);
+}
+
+#[test]
+fn dry_run_changes_nothing() {
+    let dir = TempDir::new();
+    let existing = dir.mkdir("existing");
+    let search = dir.mkdir("search");
+    let output = dir.mkdir("output");
+    write_jpeg(&search.join("IMG_1.jpg"), "2024:03:15 10:00:00", 0);
+
+    let stats = import_media_files(&config(&existing, &search, &output, "dry_run = true")).unwrap();
+
+    assert_eq!(stats.copied, 1);
+    assert!(files_below(&output).is_empty());
+}
+
+#[cfg(unix)]
+#[test]
+fn passes_paths_verbatim_to_the_post_copy_command() {
+    let dir = TempDir::new();
+    let existing = dir.mkdir("existing");
+    let search = dir.mkdir("search");
+    let output = dir.join("output");
+    let hooked = dir.join("hooked copy.jpg");
+    let name = "IMG 1 $HOME;'x' \"y\" %TEMP%.jpg";
+    write_jpeg(&search.join(name), "2024:03:15 10:00:00", 0);
+
+    let command = format!(
+        r#"post_copy_command = """cp {{path}} '{}'""""#,
+        hooked.display()
+    );
+    let stats = import_media_files(&config(&existing, &search, &output, &command)).unwrap();
+
+    assert_eq!(stats.copied, 1);
+    assert_eq!(
+        fs::read(hooked).unwrap(),
+        fs::read(output.join("2024_03_15").join(name)).unwrap()
+    );
+}
+
+#[test]
+fn cancelling_while_indexing_imports_nothing() {
+    use std::sync::{Arc, atomic::AtomicBool};
+
+    use media_tools::{ImportObserver, import_media_files_with_observer};
+
+    struct Cancelled(Arc<AtomicBool>);
+
+    impl ImportObserver for Cancelled {
+        fn cancel_flag(&self) -> Option<Arc<AtomicBool>> {
+            Some(self.0.clone())
+        }
+    }
+
+    let dir = TempDir::new();
+    let existing = dir.mkdir("existing");
+    let search = dir.mkdir("search");
+    let output = dir.join("output");
+    write_jpeg(&existing.join("IMG_1.jpg"), "2024:03:15 10:00:00", 0);
+    write_jpeg(&search.join("IMG_1.jpg"), "2024:03:15 10:00:00", 0);
+
+    let mut observer = Cancelled(Arc::new(AtomicBool::new(true)));
+    let config = config(&existing, &search, &output, "");
+    let stats = import_media_files_with_observer(&config, &mut observer).unwrap();
+
+    assert!(stats.cancelled);
+    // Indexing stopped before the first file
+    assert_eq!(stats.indexed, 0);
+    assert_eq!(stats.copied, 0);
+    assert!(!output.exists());
+}
+
+#[test]
+fn skips_files_before_since() {
+    let dir = TempDir::new();
+    let existing = dir.mkdir("existing");
+    let search = dir.mkdir("search");
+    let output = dir.join("output");
+    write_jpeg(&search.join("OLD.jpg"), "2023:12:31 23:00:00", 0);
+    write_jpeg(&search.join("NEW.jpg"), "2024:01:02 10:00:00", 0);
+
+    let config = config(
+        &existing,
+        &search,
+        &output,
+        "since = '2024-01-01T00:00:00+00:00'",
+    );
+    let stats = import_media_files(&config).unwrap();
+
+    assert_eq!(stats.copied, 1);
+    assert_eq!(stats.skipped, 1);
+    assert_eq!(files_below(&output), ["2024_01_02/NEW.jpg"]);
+}
+
+#[test]
+fn continues_after_the_latest_plausible_output_date() {
+    let dir = TempDir::new();
+    let existing = dir.mkdir("existing");
+    let search = dir.mkdir("search");
+    let output = dir.mkdir("output");
+    write_jpeg(
+        &output.join("2024_03_15/IMG_1.jpg"),
+        "2024:03:15 10:00:00",
+        0,
+    );
+    // A corrupted date far in the future
+    write_jpeg(
+        &output.join("2099_01_01/IMG_2.jpg"),
+        "2099:01:01 00:00:00",
+        0,
+    );
+    write_jpeg(&search.join("OLD.jpg"), "2024:03:14 10:00:00", 0);
+    write_jpeg(&search.join("NEW.jpg"), "2024:03:16 10:00:00", 0);
+
+    let stats =
+        import_media_files(&config(&existing, &search, &output, "auto_since = true")).unwrap();
+
+    assert_eq!(stats.copied, 1);
+    assert_eq!(
+        files_below(&output),
+        [
+            "2024_03_15/IMG_1.jpg",
+            "2024_03_16/NEW.jpg",
+            "2099_01_01/IMG_2.jpg"
+        ]
+    );
+}
+
+#[test]
+fn imports_only_files_of_the_configured_weekdays() {
+    let dir = TempDir::new();
+    let existing = dir.mkdir("existing");
+    let search = dir.mkdir("search");
+    let output = dir.join("output");
+    write_jpeg(&search.join("FRI.jpg"), "2024:03:15 23:00:00", 0);
+    write_jpeg(&search.join("SAT.jpg"), "2024:03:16 10:00:00", 0);
+    write_jpeg(&search.join("SUN.jpg"), "2024:03:17 10:00:00", 0);
+    write_jpeg(&search.join("MON.jpg"), "2024:03:18 10:00:00", 0);
+
+    let extra = "weekdays = ['Sat', 'Sunday']";
+    let stats = import_media_files(&config(&existing, &search, &output, extra)).unwrap();
+
+    assert_eq!(stats.copied, 2);
+    assert_eq!(stats.skipped, 2);
+    assert_eq!(
+        files_below(&output),
+        ["2024_03_16/SAT.jpg", "2024_03_17/SUN.jpg"]
+    );
+}
+
+#[test]
+fn compares_creation_times_by_the_second() {
+    let dir = TempDir::new();
+    let existing = dir.mkdir("existing");
+    let search = dir.mkdir("search");
+    let date = (DATE_TIME_ORIGINAL, "2024:03:15 10:00:00");
+    write_jpeg_with_tags(
+        &existing.join("IMG_1.jpg"),
+        &[date, (SUB_SEC_TIME_ORIGINAL, "10")],
+        100,
+    );
+    write_jpeg_with_tags(
+        &search.join("IMG_1.jpg"),
+        &[date, (SUB_SEC_TIME_ORIGINAL, "60")],
+        0,
+    );
+
+    // The smaller file is a lower-quality version of the same shot
+    let output = dir.join("seconds");
+    let stats = import_media_files(&config(&existing, &search, &output, "")).unwrap();
+    assert_eq!(stats.copied, 0);
+    assert_eq!(stats.duplicates[0].reason, SkipReason::LowerQuality);
+
+    // Another shot of the same second
+    let output = dir.join("subseconds");
+    let config = config(&existing, &search, &output, "subsecond_precision = true");
+    let stats = import_media_files(&config).unwrap();
+    assert_eq!(stats.copied, 1);
+    assert_eq!(stats.ambiguous.len(), 1);
+}
+
+#[test]
+fn preserves_timestamps() {
+    let dir = TempDir::new();
+    let existing = dir.mkdir("existing");
+    let search = dir.mkdir("search");
+    let output = dir.join("output");
+    let source = search.join("IMG_1.jpg");
+    write_jpeg(&source, "2024:03:15 10:00:00", 0);
+    let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    File::options()
+        .write(true)
+        .open(&source)
+        .unwrap()
+        .set_times(FileTimes::new().set_modified(modified))
+        .unwrap();
+
+    let config = config(&existing, &search, &output, "preserve_timestamps = true");
+    import_media_files(&config).unwrap();
+
+    let target = output.join("2024_03_15/IMG_1.jpg");
+    assert_eq!(fs::metadata(target).unwrap().modified().unwrap(), modified);
+}
+
+#[test]
+fn applies_planned_decisions_one_by_one() {
+    let dir = TempDir::new();
+    let existing = dir.mkdir("existing");
+    let search = dir.mkdir("search");
+    let output = dir.join("output");
+    write_jpeg(&existing.join("IMG_1.jpg"), "2024:03:15 10:00:00", 0);
+    write_jpeg(&search.join("IMG_1.jpg"), "2024:03:15 10:00:00", 0);
+    write_jpeg(&search.join("IMG_2.jpg"), "2024:03:15 10:05:00", 0);
+    write_jpeg(&search.join("IMG_3.jpg"), "2024:03:15 10:10:00", 0);
+    let config = config(&existing, &search, &output, "");
+
+    let decisions: Vec<_> = plan_import(&config)
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(decisions.len(), 3);
+    assert!(!output.exists());
+
+    assert_eq!(
+        decisions[0],
+        ImportDecision::Skip {
+            source: search.join("IMG_1.jpg"),
+            reason: SkipReason::Duplicate,
+            existing: Some(existing.join("IMG_1.jpg")),
+        }
+    );
+    assert!(matches!(&decisions[1], ImportDecision::Copy { target, .. }
+        if *target == output.join("2024_03_15/IMG_2.jpg")));
+    assert_eq!(decisions[0].apply(&config).unwrap(), None);
+    assert_eq!(
+        decisions[1].apply(&config).unwrap(),
+        Some(output.join("2024_03_15/IMG_2.jpg"))
+    );
+    assert_eq!(files_below(&output), ["2024_03_15/IMG_2.jpg"]);
+}
+
+#[cfg(unix)]
+#[test]
+fn hard_links_files_on_the_same_device() {
+    use std::os::unix::fs::MetadataExt;
+
+    let dir = TempDir::new();
+    let existing = dir.mkdir("existing");
+    let search = dir.mkdir("search");
+    let output = dir.join("output");
+    write_jpeg(&search.join("IMG_1.jpg"), "2024:03:15 10:00:00", 0);
+
+    let stats =
+        import_media_files(&config(&existing, &search, &output, "link_mode = 'hard'")).unwrap();
+
+    let source = fs::metadata(search.join("IMG_1.jpg")).unwrap();
+    let target = fs::metadata(output.join("2024_03_15/IMG_1.jpg")).unwrap();
+    assert_eq!(stats.copied, 1);
+    assert_eq!(stats.bytes_linked, source.len());
+    assert_eq!((target.dev(), target.ino()), (source.dev(), source.ino()));
+}
+
+#[test]
+fn reads_file_sizes_like_the_metadata() {
+    let dir = TempDir::new();
+    let existing = dir.mkdir("existing");
+    let search = dir.mkdir("search");
+    let output = dir.join("output");
+    let paths = [0, 1, 4096, 100_000].map(|padding| {
+        let path = search.join(format!("IMG_{padding}.jpg"));
+        write_jpeg(&path, "2024:03:15 10:00:00", padding);
+        path
+    });
+
+    let config = config(&existing, &search, &output, "");
+    for path in &paths {
+        let file = MediaFile::try_from_path(path, &config).unwrap();
+        assert_eq!(file.size, fs::metadata(path).unwrap().len());
+    }
+    let total: u64 = paths.iter().map(|p| fs::metadata(p).unwrap().len()).sum();
+    assert_eq!(count_media_files(&config).unwrap().bytes, total);
+}
+
+#[test]
+fn counts_media_files_without_importing() {
+    let dir = TempDir::new();
+    let existing = dir.mkdir("existing");
+    let search = dir.mkdir("search");
+    let output = dir.mkdir("search/output");
+    write_jpeg(&search.join("IMG_1.jpg"), "2024:03:15 10:00:00", 0);
+    write_jpeg(&search.join("nested/IMG_2.jpg"), "2024:03:16 11:30:00", 0);
+    write_jpeg(
+        &output.join("2024_03_15/IMG_1.jpg"),
+        "2024:03:15 10:00:00",
+        0,
+    );
+    fs::write(search.join("notes.txt"), "not a media file").unwrap();
+
+    let count = count_media_files(&config(&existing, &search, &output, "")).unwrap();
+
+    let size = fs::metadata(search.join("IMG_1.jpg")).unwrap().len();
+    assert_eq!(count.files, 2);
+    assert_eq!(count.bytes, 2 * size);
+    assert_eq!(files_below(&output), ["2024_03_15/IMG_1.jpg"]);
+}
+
+#[cfg(feature = "backfill-exif")]
+#[test]
+fn backfills_exif_dates_of_copies() {
+    use media_tools::{MediaFile, import_media_files_reporting};
+
+    let dir = TempDir::new();
+    let existing = dir.mkdir("existing");
+    let search = dir.mkdir("search");
+    let output = dir.join("output");
+    // A JPEG without exif data, dated by its modification time
+    let source = search.join("IMG_1.jpg");
+    let mut jpeg = vec![0xff, 0xd8, 0xff, 0xda, 0x00, 0x08];
+    jpeg.extend([0; 64]);
+    jpeg.extend([0xff, 0xd9]);
+    fs::write(&source, &jpeg).unwrap();
+    let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    File::options()
+        .write(true)
+        .open(&source)
+        .unwrap()
+        .set_times(FileTimes::new().set_modified(modified))
+        .unwrap();
+
+    let config = config(&existing, &search, &output, "backfill_exif = true");
+    let targets = import_media_files_reporting(&config).unwrap();
+
+    assert_eq!(targets.len(), 1);
+    assert_eq!(fs::read(&source).unwrap(), jpeg);
+    let created = MediaFile::try_from_path(&targets[0], &config)
+        .unwrap()
+        .created;
+    assert_eq!(created.timestamp(), 1_700_000_000);
+
+    // The backfilled copy is recognized as the same file
+    let stats = import_media_files(&config).unwrap();
+    assert_eq!(stats.copied, 0);
+    assert_eq!(stats.duplicates[0].reason, SkipReason::Duplicate);
+}
+
+#[cfg(feature = "auto-rotate")]
+#[test]
+fn rotates_copies_losslessly() {
+    use image::{ImageDecoder as _, ImageReader, metadata::Orientation};
+
+    let dir = TempDir::new();
+    let existing = dir.mkdir("existing");
+    let search = dir.mkdir("search");
+    let output = dir.join("output");
+    // Taken with the camera rotated by 90 degrees clockwise
+    let source = search.join("IMG_1.jpg");
+    common::write_photo(&source, "2024:03:15 10:00:00", (32, 16), 6);
+
+    let config = config(&existing, &search, &output, "auto_rotate = true");
+    let stats = import_media_files(&config).unwrap();
+
+    assert_eq!(stats.copied, 1);
+    let target = output.join("2024_03_15/IMG_1.jpg");
+    let mut decoder = ImageReader::open(&target).unwrap().into_decoder().unwrap();
+    assert_eq!(decoder.orientation().unwrap(), Orientation::NoTransforms);
+    let rotated = image::open(&target).unwrap().to_rgb8();
+    let expected = image::imageops::rotate90(&image::open(&source).unwrap().to_rgb8());
+    assert_eq!(rotated.dimensions(), (16, 32));
+    // Only the upsampling of the chroma differs between the orientations
+    let difference = rotated
+        .as_raw()
+        .iter()
+        .zip(expected.as_raw())
+        .map(|(a, b)| a.abs_diff(*b))
+        .max();
+    assert!(difference < Some(8), "pixels differ by {difference:?}");
+
+    // The rotated copy is recognized as the same file
+    let stats = import_media_files(&config).unwrap();
+    assert_eq!(stats.copied, 0);
+    assert_eq!(stats.duplicates[0].reason, SkipReason::Duplicate);
+}
+
+#[cfg(feature = "auto-rotate")]
+#[test]
+fn keeps_the_orientation_of_malformed_jpeg_files() {
+    use common::{write_jpeg_segments, write_photo};
+
+    let dir = TempDir::new();
+    let existing = dir.mkdir("existing");
+    let search = dir.mkdir("search");
+    let output = dir.join("output");
+    let date = "2024:03:15 10:00:00";
+
+    // The scan ends early
+    let truncated = search.join("TRUNCATED.jpg");
+    write_photo(&truncated, date, (32, 16), 6);
+    let data = fs::read(&truncated).unwrap();
+    fs::write(&truncated, &data[..data.len() - 40]).unwrap();
+
+    // Tables of a single symbol each, coded as `0`
+    let quantization = [[0].as_slice(), &[1; 64]].concat();
+    let table = |class_id: u8, symbol: u8| {
+        let mut table = vec![class_id, 1];
+        table.extend([0; 15]);
+        table.push(symbol);
+        table
+    };
+    let scan_header = [1, 1, 0x00, 0, 63, 0];
+    let (dc_table, ac_table) = (table(0x00, 0), table(0x10, 0x00));
+
+    // A DC coefficient of 40 bits
+    let bad_dc_table = table(0x00, 40);
+    let frame = [8, 0, 8, 0, 8, 1, 1, 0x11, 0];
+    write_jpeg_segments(
+        &search.join("DC.jpg"),
+        date,
+        6,
+        &[
+            (0xdb, &quantization),
+            (0xc0, &frame),
+            (0xc4, &bad_dc_table),
+            (0xc4, &ac_table),
+            (0xda, &scan_header),
+        ],
+        &[0; 16],
+    );
+
+    // A frame of 65535x65535 pixels with a few bytes of data
+    let huge_frame = [8, 0xff, 0xff, 0xff, 0xff, 1, 1, 0x44, 0];
+    write_jpeg_segments(
+        &search.join("HUGE.jpg"),
+        date,
+        6,
+        &[
+            (0xdb, &quantization),
+            (0xc0, &huge_frame),
+            (0xc4, &dc_table),
+            (0xc4, &ac_table),
+            (0xda, &scan_header),
+        ],
+        &[0; 16],
+    );
+
+    let stats =
+        import_media_files(&config(&existing, &search, &output, "auto_rotate = true")).unwrap();
+
+    assert_eq!(stats.errors, 0);
+    assert_eq!(stats.copied, 3);
+    for name in ["DC.jpg", "HUGE.jpg", "TRUNCATED.jpg"] {
+        assert_eq!(
+            fs::read(output.join("2024_03_15").join(name)).unwrap(),
+            fs::read(search.join(name)).unwrap(),
+            "{name} was changed"
+        );
+    }
+}